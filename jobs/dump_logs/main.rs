@@ -1,16 +1,59 @@
 use chatan::overrustle::{DataLoadMode, OverRustleLogs};
+use chatan::compression::Compression;
 
 use chatan::util::*;
 
 fn main() {
     let path = "D:\\overrustle-dump";
 
-    let channels: Vec<String> = std::env::args().skip(1).collect();
+    // `--full-resync` ignores persisted indexes and scrapes everything again
+    let full_resync = std::env::args().any(|a| a == "--full-resync");
+    // `--refresh-days=N` downloads last N days again, as they might be incomplete
+    let refresh_days = std::env::args()
+        .find(|a| a.starts_with("--refresh-days="))
+        .map(|a| a["--refresh-days=".len()..].parse::<i64>().expect("Invalid number of days"));
+    let mut args = std::env::args().skip(1).filter(|a| !a.starts_with("--")).peekable();
+
+    // `dump recompress <none|gzip|zstd> channels...` rewrites already dumped files
+    let recompress = if args.peek().map_or(false, |a| a == "recompress") {
+        args.next();
+        let compression = args.next().expect("Compression is required for recompress");
+        Some(compression.parse::<Compression>().expect("Unknown compression"))
+    } else {
+        None
+    };
+
+    let channels: Vec<String> = args.collect();
 
     progress_bar(true);
 
     for channel in channels.into_iter() {
-        let logs = OverRustleLogs::make_and_sync(path.into(), channel, DataLoadMode::PrefetchAndCache);
-        println!("{}", logs);
+        match recompress {
+            Some(compression) => {
+                let mut logs = OverRustleLogs::make_and_sync(path.into(), channel, DataLoadMode::Local)
+                    .expect("Could not sync logs");
+                logs.recompress(compression).expect("Could not recompress logs");
+                println!("{}", logs);
+            },
+            None => {
+                let mut logs = OverRustleLogs::new(path.into(), channel.clone(), DataLoadMode::PrefetchAndCache)
+                    .with_full_resync(full_resync);
+                if let Some(days) = refresh_days {
+                    logs = logs.with_refresh_horizon(chrono::Duration::days(days));
+                }
+                match logs.sync() {
+                    Ok(_) => {
+                        println!("{}", logs);
+                        if let Some(err) = logs.manifest_error() {
+                            eprintln!("Could not load persisted index for {}, did full resync: {}", channel, err);
+                        }
+                        for (date, err) in logs.failed_downloads() {
+                            eprintln!("Could not download {} for {}: {}", date.format("%Y-%m-%d"), channel, err);
+                        }
+                    },
+                    Err(err) => eprintln!("Could not sync logs for {}: {}", channel, err)
+                }
+            }
+        }
     }
 }
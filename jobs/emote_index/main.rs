@@ -2,10 +2,13 @@ extern crate structopt;
 use structopt::StructOpt;
 
 extern crate chatan;
-use chatan::overrustle::{DataLoadMode, OverRustleLogs};
+use chatan::overrustle::{DataLoadMode, OverRustleLogs, DEFAULT_BASE_URL};
 use chatan::emote_index;
 use chatan::emote_index::{load_index, EmoteProvider, update_index_in_path};
-use chatan::chatlog::DailyChatLog;
+use chatan::chatlog::{ChatLog, DirectoryLog, TokenCountOptions};
+use chatan::message::LogFormat;
+use chatan::tokenizer::Whitespace;
+use chatan::filter::{FilterOpt, FilteredLog};
 
 use std::path::PathBuf;
 use chrono::{DateTime, Utc};
@@ -43,9 +46,22 @@ enum OperationMode {
         #[structopt(name = "top", long)]
         top: u32,
         #[structopt(name = "storage", long)]
-        storage: PathBuf,
+        storage: Option<PathBuf>,
         #[structopt(name = "storage-policy", long)]
-        storage_policy: DataLoadMode,
+        storage_policy: Option<DataLoadMode>,
+        /// Read logs from a local directory instead of OverRustle
+        #[structopt(name = "directory", long)]
+        directory: Option<PathBuf>,
+        #[structopt(name = "filename-pattern", long, default_value = "%Y-%m-%d.txt")]
+        filename_pattern: String,
+        #[structopt(name = "format", long, default_value = "overrustle")]
+        format: LogFormat,
+        #[structopt(name = "base-url", long)]
+        base_url: Option<String>,
+        #[structopt(name = "full-resync", long)]
+        full_resync: bool,
+        #[structopt(flatten)]
+        filters: FilterOpt,
     },
 }
 
@@ -54,9 +70,13 @@ enum OperationMode {
 /// result anymore because APIs do not emit them.
 ///
 /// TODO parameters are mess, cleanup/split into several functions
-pub fn discover_lost_emotes(
-    base_index: EmoteIndex, logs: &mut OverRustleLogs, start: DateTime<Utc>, end: DateTime<Utc>, top: u32,
+pub fn discover_lost_emotes<L: ChatLog>(
+    base_index: EmoteIndex, logs: &mut L, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>, top: u32,
 ) -> EmoteIndex {
+    let (log_start, log_end) = logs.span().expect("Logs are empty, can't discover anything");
+    let start = start.unwrap_or(log_start);
+    let end = end.unwrap_or(log_end);
+
     let client = Client::new();
 
     // only providers which do not store historical emotes
@@ -81,11 +101,11 @@ pub fn discover_lost_emotes(
                         .collect::<HashSet<String>>()
                 );
             },
-            |tok| 2 <= tok.len()
+            &TokenCountOptions::new(&Whitespace).with_token_filter(|tok: &str| 2 <= tok.len()
                 && tok.len() <= 32
                 && tok.chars().all(|c| c.is_ascii_alphanumeric())
-                && !base_index.contains_key(tok)
-        ).ok().expect("Failed to iterate through the logs");
+                && !base_index.contains_key(tok))
+        ).unwrap_or_else(|err| panic!("Failed to iterate through the logs: {}", err));
 
         println!("{} popular tokens found", result.len());
 
@@ -123,20 +143,35 @@ fn main() {
             update_index_in_path(&client, vec![opt.channel], providers, opt.output.as_path(), input)
                 .expect("Could not update index in path");
         },
-        OperationMode::Discover
-        { start, end, top, storage, storage_policy, .. } => {
-            let mut logs = OverRustleLogs::make_and_sync(storage, opt.channel.clone(), storage_policy);
+        OperationMode::Discover {
+            start, end, top, storage, storage_policy, base_url, full_resync, directory, filename_pattern, format, filters
+        } => {
             let base_index = match opt.input {
                 Some(input) => load_index(&input).expect("Could not load input index"),
                 None => EmoteIndex::new()
             };
-            let (log_start, log_end) = logs.range().expect("Logs are empty, can't discover anything");
-            let start = start.unwrap_or(log_start.and_hms(0, 0, 0));
-            let end = end.unwrap_or(log_end.and_hms(0, 0, 0));
-            emote_index::save_index(
-                &opt.output,
-                &discover_lost_emotes(base_index, &mut logs, start, end, top)
-            ).expect("Could not save index to output file");
+            let index = match directory {
+                Some(directory) => {
+                    let logs = DirectoryLog::open(directory, &filename_pattern, format.line_parser())
+                        .expect("Could not open log directory");
+                    discover_lost_emotes(base_index, &mut FilteredLog::new(logs, filters.pipeline()), start, end, top)
+                },
+                None => {
+                    let mut logs = OverRustleLogs::new(
+                        storage.expect("--storage is required for OverRustle logs"),
+                        opt.channel.clone(),
+                        storage_policy.expect("--storage-policy is required for OverRustle logs")
+                    )
+                        .with_base_url(base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string()))
+                        .with_full_resync(full_resync);
+                    logs.sync().expect("Could not sync logs");
+                    if let Some(err) = logs.manifest_error() {
+                        eprintln!("Could not load persisted index for {}, did full resync: {}", opt.channel, err);
+                    }
+                    discover_lost_emotes(base_index, &mut FilteredLog::new(logs, filters.pipeline()), start, end, top)
+                }
+            };
+            emote_index::save_index(&opt.output, &index).expect("Could not save index to output file");
         }
     };
 }
\ No newline at end of file
@@ -2,41 +2,139 @@ extern crate chatan;
 extern crate structopt;
 use structopt::StructOpt;
 
-use chatan::overrustle::{OverRustleLogs, DataLoadMode};
-use crate::chatan::chatlog::DailyChatLog;
+use chatan::overrustle::{OverRustleLogs, DataLoadMode, DEFAULT_BASE_URL};
+use crate::chatan::chatlog::{ChatLog, DirectoryLog, MergedChatLog};
 use chrono::{Utc, DateTime};
 
 use std::path::PathBuf;
 use counter::Counter;
 use std::fs::File;
 use serde::Serialize;
-use chatan::chatlog::WindowStats;
+use chatan::chatlog::{CountingMode, TokenCountOptions, WindowStats, UserWindowStats, window_stats, window_ngrams, window_phrases, window_user_stats};
+use chatan::message::{Message, LogFormat};
+use chatan::emote_index::EmoteIndex;
+use chatan::filter::{FilterOpt, FilteredLog};
+use chatan::tokenizer::{Tokenizer, Whitespace, UnicodeWhitespace, UnicodeWords, EmoteAware, Lowercase};
+use std::str::FromStr;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::collections::{BTreeMap, HashSet};
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Compute a rolling top of specific tokens from logs")]
 struct RollingTop {
     #[structopt(subcommand)]
     mode: Mode,
-    #[structopt(name = "channel", long)]
-    channel: String,
+    /// Can be given several times, in which case logs of all the channels are combined
+    #[structopt(name = "channel", long, number_of_values = 1)]
+    channels: Vec<String>,
+    /// Compute separate top for each channel instead of combining them, OverRustle logs only
+    #[structopt(name = "per-channel", long, conflicts_with = "directory")]
+    per_channel: bool,
     #[structopt(name = "start", long)]
     start: Option<DateTime<Utc>>,
     #[structopt(name = "end", long)]
     end: Option<DateTime<Utc>>,
     #[structopt(name = "step", long)]
-    step: u32,
+    step: Option<u32>,
     #[structopt(name = "size", long)]
-    size: u32,
+    size: Option<u32>,
+    /// Use chat sessions separated by this many minutes of inactivity as windows, instead of
+    /// fixed `step` and `size`
+    #[structopt(name = "session-gap", long)]
+    session_gap: Option<u32>,
+    /// Use windows of this many messages, instead of fixed `step` and `size`
+    #[structopt(name = "window-messages", long)]
+    window_messages: Option<usize>,
     #[structopt(name = "top", long)]
     n_top: u64,
     #[structopt(name = "data-load-mode", long)]
-    data_load_mode: DataLoadMode,
+    data_load_mode: Option<DataLoadMode>,
     #[structopt(name = "frequency-threshold", long)]
     threshold: u64,
     #[structopt(name = "output", long)]
     output_file: PathBuf,
     #[structopt(name = "cache-dir", long)]
-    cache_dir: PathBuf
+    cache_dir: Option<PathBuf>,
+    /// Read logs from a local directory instead of OverRustle
+    #[structopt(name = "directory", long)]
+    directory: Option<PathBuf>,
+    #[structopt(name = "filename-pattern", long, default_value = "%Y-%m-%d.txt")]
+    filename_pattern: String,
+    #[structopt(name = "format", long, default_value = "overrustle")]
+    format: LogFormat,
+    #[structopt(name = "base-url", long)]
+    base_url: Option<String>,
+    #[structopt(name = "full-resync", long)]
+    full_resync: bool,
+    #[structopt(name = "audience", long, default_value = "all")]
+    audience: Audience,
+    /// How tokens are counted in `emotes` and `tokens` modes: `occurrences`, `messages` (once
+    /// per message) or `users` (once per user in a window)
+    #[structopt(name = "counting", long, default_value = "occurrences")]
+    counting: CountingMode,
+    /// Evaluate windows in parallel. Windows are counted from scratch, so this is mostly
+    /// useful when step is comparable to window size
+    #[structopt(name = "parallel", long)]
+    parallel: bool,
+    #[structopt(flatten)]
+    filters: FilterOpt,
+}
+
+/// Whose messages should be counted. Subscriber status is only known for logs with metadata,
+/// messages without it are treated as sent by non-subscribers.
+#[derive(Debug, Clone, Copy)]
+enum Audience {
+    All,
+    Subscribers,
+    NonSubscribers,
+}
+
+impl FromStr for Audience {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Audience::All),
+            "subscribers" => Ok(Audience::Subscribers),
+            "nonsubscribers" => Ok(Audience::NonSubscribers),
+            _ => Err(s.to_string())
+        }
+    }
+}
+
+impl Audience {
+    fn includes(&self, message: &Message) -> bool {
+        match self {
+            Audience::All => true,
+            Audience::Subscribers => message.is_subscriber(),
+            Audience::NonSubscribers => !message.is_subscriber(),
+        }
+    }
+}
+
+/// How messages are split into tokens in `tokens` mode
+#[derive(Debug, Clone, Copy)]
+enum TokenizerKind {
+    Whitespace,
+    UnicodeWhitespace,
+    Words,
+    Emotes,
+}
+
+impl FromStr for TokenizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "whitespace" => Ok(TokenizerKind::Whitespace),
+            "unicode-whitespace" => Ok(TokenizerKind::UnicodeWhitespace),
+            "words" => Ok(TokenizerKind::Words),
+            "emotes" => Ok(TokenizerKind::Emotes),
+            _ => Err(s.to_string())
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -47,9 +145,53 @@ enum Mode {
         index: PathBuf,
     },
     #[structopt(name = "tokens")]
-    Tokens,
+    Tokens {
+        #[structopt(flatten)]
+        tokenizer: TokenizerOpt,
+    },
+    /// Sequences of `n` consecutive tokens of a message
+    #[structopt(name = "ngrams")]
+    NGrams {
+        #[structopt(name = "n", long = "n", default_value = "2")]
+        n: usize,
+        #[structopt(flatten)]
+        tokenizer: TokenizerOpt,
+    },
+    /// Whole messages repeated by different users, e.g. copypastas
+    #[structopt(name = "phrases")]
+    Phrases {
+        #[structopt(name = "min-tokens", long, default_value = "3")]
+        min_tokens: usize,
+        #[structopt(flatten)]
+        tokenizer: TokenizerOpt,
+    },
     #[structopt(name = "messages")]
     Messages,
+    /// Chatters who sent most messages, along with numbers of unique, new and returning chatters
+    #[structopt(name = "users")]
+    Users,
+}
+
+#[derive(Debug, StructOpt)]
+struct TokenizerOpt {
+    /// One of `whitespace` (ASCII whitespace), `unicode-whitespace`, `words` (Unicode word
+    /// boundaries) or `emotes` (words, but emote codes from `index` are kept intact)
+    #[structopt(name = "tokenizer", long, default_value = "whitespace")]
+    kind: TokenizerKind,
+    /// Count tokens case-insensitively
+    #[structopt(name = "lowercase", long)]
+    lowercase: bool,
+    #[structopt(name = "index", long)]
+    index: Option<PathBuf>,
+}
+
+impl Mode {
+    fn tokenizer_opt(&self) -> Option<&TokenizerOpt> {
+        match self {
+            Mode::Tokens { tokenizer } | Mode::NGrams { tokenizer, .. } | Mode::Phrases { tokenizer, .. } => Some(tokenizer),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -57,58 +199,231 @@ struct RollingTopWords {
     t0: DateTime<Utc>,
     t1: DateTime<Utc>,
     data: Vec<(String, u64)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chatters: Option<ChatterCounts>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatterCounts {
+    unique: u64,
+    new: u64,
+    returning: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Output {
+    Combined(Vec<RollingTopWords>),
+    PerChannel(BTreeMap<String, Vec<RollingTopWords>>),
 }
 
 impl RollingTopWords {
     fn new(t0: DateTime<Utc>, t1: DateTime<Utc>, data: Vec<(String, u64)>) -> RollingTopWords {
-        RollingTopWords { t0, t1, data }
+        RollingTopWords { t0, t1, data, chatters: None }
     }
 }
 
-fn convert_counter(t0: &DateTime<Utc>, t1: &DateTime<Utc>, thr: u64, top: u64, counter: Counter<&str, u64>) -> RollingTopWords {
+fn convert_counter<T: Display + Hash + Eq + Clone>(
+    t0: &DateTime<Utc>, t1: &DateTime<Utc>, thr: u64, top: u64, counter: Counter<T, u64>
+) -> RollingTopWords {
     let most_common = chatan::util::most_common(counter, thr);
     let mut top_tokens: Vec<(String, u64)> = Vec::with_capacity(top as usize);
     most_common.iter().take(top as usize).for_each(|(s, n)| top_tokens.push((s.to_string(), *n)));
     RollingTopWords::new(*t0, *t1, top_tokens)
 }
 
+fn convert_user_stats(t0: &DateTime<Utc>, t1: &DateTime<Utc>, thr: u64, top: u64, stats: UserWindowStats) -> RollingTopWords {
+    let messages: Counter<&str, u64> = stats.chatters.iter().map(|(u, s)| (*u, s.n_messages)).collect();
+    let mut result = convert_counter(t0, t1, thr, top, messages);
+    result.chatters = Some(ChatterCounts {
+        unique: stats.n_chatters(),
+        new: stats.n_new_chatters,
+        returning: stats.n_returning_chatters,
+    });
+    result
+}
+
 fn main() {
     let opt = RollingTop::from_args();
 
-    let mut logs = OverRustleLogs::make_and_sync(
-        opt.cache_dir.clone(), opt.channel.clone(), opt.data_load_mode
-    );
+    let output = match opt.directory.as_ref() {
+        Some(directory) => {
+            let logs = DirectoryLog::open(directory.clone(), &opt.filename_pattern, opt.format.line_parser())
+                .expect("Could not open log directory");
+            Output::Combined(roll(&mut FilteredLog::new(logs, opt.filters.pipeline()), &opt))
+        },
+        None => {
+            assert!(!opt.channels.is_empty(), "At least one --channel is required for OverRustle logs");
+            let cache_dir = opt.cache_dir.clone().expect("--cache-dir is required for OverRustle logs");
+            let mode = opt.data_load_mode.expect("--data-load-mode is required for OverRustle logs");
+
+            let mut sources = Vec::new();
+            for channel in opt.channels.iter() {
+                let mut logs = OverRustleLogs::new(cache_dir.clone(), channel.clone(), mode)
+                    .with_base_url(opt.base_url.clone().unwrap_or_else(|| DEFAULT_BASE_URL.to_string()))
+                    .with_full_resync(opt.full_resync);
+                logs.sync().expect("Could not sync logs");
+                if let Some(err) = logs.manifest_error() {
+                    eprintln!("Could not load persisted index for {}, did full resync: {}", channel, err);
+                }
+                println!("{}", &logs);
+                sources.push((channel.clone(), logs));
+            }
+
+            if opt.per_channel {
+                Output::PerChannel(sources
+                    .into_iter()
+                    .map(|(channel, logs)| (channel, roll(&mut FilteredLog::new(logs, opt.filters.pipeline()), &opt)))
+                    .collect())
+            } else {
+                Output::Combined(roll(&mut FilteredLog::new(MergedChatLog::new(sources), opt.filters.pipeline()), &opt))
+            }
+        }
+    };
+
+    let file = File::create(&opt.output_file)
+        .expect("Could not create output file");
 
-    println!("{}", &logs);
+    serde_json::to_writer(file, &output)
+        .expect("Could not write output file");
+
+//    let mut wrt = csv::Writer::from_writer(file);
+//    for el in &result {
+//        wrt.serialize(el).expect("Could not write output file");
+//    }
+}
+
+/// Count tokens of messages sent by `audience`
+fn token_count_options<'t>(
+    audience: Audience, counting: CountingMode, tokenizer: &'t (dyn Tokenizer + 't)
+) -> TokenCountOptions<'t, dyn Tokenizer + 't, impl Fn(&Message) -> bool> {
+    TokenCountOptions::new(tokenizer)
+        .with_counting(counting)
+        .with_message_filter(move |m: &Message| audience.includes(m))
+}
 
-    let (logs_first, logs_last) = logs.range().unwrap();
+/// Count messages or tokens of a window, depending on mode
+fn count_window<'a>(
+    mode: &Mode, audience: Audience, counting: CountingMode, emote_index: &EmoteIndex, tokenizer: &dyn Tokenizer,
+    win: &mut dyn Iterator<Item=&'a Message>
+) -> Counter<Cow<'a, str>, u64> {
+    match mode {
+        Mode::Messages => win
+            .filter(|m| audience.includes(m))
+            .map(|m| Cow::Borrowed(m.message()))
+            .collect(),
+        Mode::Emotes { .. } => window_stats(
+            win, &token_count_options(audience, counting, tokenizer).with_token_filter(|t: &str| emote_index.contains_key(t))
+        ).token_counts,
+        Mode::Tokens { .. } => window_stats(win, &token_count_options(audience, counting, tokenizer)).token_counts,
+        Mode::NGrams { n, .. } => window_ngrams(win, &|m: &Message| audience.includes(m), tokenizer, *n),
+        Mode::Phrases { min_tokens, .. } => window_phrases(
+            win, &|m: &Message| audience.includes(m), tokenizer, *min_tokens
+        ),
+        Mode::Users => unreachable!("Chatters are counted by window_user_stats"),
+    }
+}
 
-    let start = opt.start.unwrap_or(logs_first.and_hms(0, 0, 0));
-    let end = opt.end.unwrap_or(logs_last.and_hms(0, 0, 0));
-    let step = opt.step;
-    let size = opt.size;
+/// Tokenizer selected by mode, emotes are always separated by whitespace
+fn make_tokenizer(mode: &Mode, emote_index: &EmoteIndex) -> Box<dyn Tokenizer> {
+    match mode.tokenizer_opt() {
+        Some(opt) => {
+            let tokenizer: Box<dyn Tokenizer> = match opt.kind {
+                TokenizerKind::Whitespace => Box::new(Whitespace),
+                TokenizerKind::UnicodeWhitespace => Box::new(UnicodeWhitespace),
+                TokenizerKind::Words => Box::new(UnicodeWords),
+                TokenizerKind::Emotes => Box::new(EmoteAware::from_index(emote_index, UnicodeWords)),
+            };
+            if opt.lowercase { Box::new(Lowercase(tokenizer)) } else { tokenizer }
+        },
+        None => Box::new(Whitespace)
+    }
+}
+
+fn roll<L: ChatLog>(logs: &mut L, opt: &RollingTop) -> Vec<RollingTopWords> {
+    let (logs_start, logs_end) = logs.span().expect("Logs are empty");
+
+    let start = opt.start.unwrap_or(logs_start);
+    let end = opt.end.unwrap_or(logs_end);
     let top = opt.n_top;
     let threshold = opt.threshold;
+    let audience = opt.audience;
+    let counting = opt.counting;
 
-    println!(
-        "Window params: start={:?} end={:?} step={:?} size={:?} ; will gather top={} token_type='{:?}' per window",
-        start, end, step, size, top, opt.mode
-    );
+    let emote_index = match (&opt.mode, opt.mode.tokenizer_opt()) {
+        (Mode::Emotes { index }, _) => chatan::emote_index::load_index(index).expect("Could not load emote index"),
+        (_, Some(TokenizerOpt { kind: TokenizerKind::Emotes, index, .. })) => {
+            let index = index.as_ref().expect("--index is required for emotes tokenizer");
+            chatan::emote_index::load_index(index).expect("Could not load emote index")
+        },
+        _ => EmoteIndex::new()
+    };
+    let tokenizer = make_tokenizer(&opt.mode, &emote_index);
 
     let mut result = Vec::new();
     let t = std::time::Instant::now();
+    // chatters of earlier windows, to tell new chatters from returning ones
+    let mut seen = HashSet::new();
 
-    match opt.mode {
-        Mode::Messages => {
-            logs.slide(
-                start, end, step, size,
-                |t0, t1, win| {
-                    println!("Window {:?} -- {:?}", t0, t1);
-                    let counter: Counter<&str, u64> = win.map(|m| m.message()).collect();
-                    result.push(convert_counter(t0, t1, threshold, top, counter))
-                }
-            )
-        },
+    let mut f = |t0: &DateTime<Utc>, t1: &DateTime<Utc>, win: &mut dyn Iterator<Item=&Message>| {
+        println!("Window {:?} -- {:?}", t0, t1);
+        result.push(match &opt.mode {
+            Mode::Users => convert_user_stats(
+                t0, t1, threshold, top,
+                window_user_stats(win, &|m: &Message| audience.includes(m), &*tokenizer, &mut seen)
+            ),
+            mode => convert_counter(t0, t1, threshold, top, count_window(mode, audience, counting, &emote_index, &*tokenizer, win))
+        })
+    };
+
+    if let Some(gap) = opt.session_gap {
+        println!(
+            "Window params: start={:?} end={:?} session_gap={}m ; will gather top={} token_type='{:?}' \
+             audience={:?} per window",
+            start, end, gap, top, opt.mode, audience
+        );
+        logs.slide_sessions(start, end, gap * 60, &mut f)
+            .unwrap_or_else(|err| panic!("Failed to slide through the logs: {}", err));
+        println!("Successfully rolled through logs in {:.3}s", t.elapsed().as_secs_f64());
+        return result;
+    }
+
+    if let Some(count) = opt.window_messages {
+        println!(
+            "Window params: start={:?} end={:?} messages={} ; will gather top={} token_type='{:?}' \
+             audience={:?} per window",
+            start, end, count, top, opt.mode, audience
+        );
+        logs.slide_message_count(start, end, count, &mut f)
+            .unwrap_or_else(|err| panic!("Failed to slide through the logs: {}", err));
+        println!("Successfully rolled through logs in {:.3}s", t.elapsed().as_secs_f64());
+        return result;
+    }
+
+    let step = opt.step.expect("--step is required for fixed windows");
+    let size = opt.size.expect("--size is required for fixed windows");
+
+    println!(
+        "Window params: start={:?} end={:?} step={:?} size={:?} ; will gather top={} token_type='{:?}' \
+         audience={:?} per window",
+        start, end, step, size, top, opt.mode, audience
+    );
+
+    if opt.parallel {
+        if let Mode::Users = opt.mode {
+            panic!("Users mode depends on earlier windows and can't be evaluated in parallel");
+        }
+        let result = logs.par_slide(
+            start, end, step, size,
+            |t0, t1, win| convert_counter(t0, t1, threshold, top, count_window(&opt.mode, audience, counting, &emote_index, &*tokenizer, win))
+        ).unwrap_or_else(|err| panic!("Failed to slide through the logs: {}", err));
+
+        println!("Successfully rolled through logs in {:.3}s", t.elapsed().as_secs_f64());
+        return result;
+    }
+
+    match &opt.mode {
+        Mode::Messages | Mode::NGrams { .. } | Mode::Phrases { .. } | Mode::Users => logs.slide(start, end, step, size, f),
         mode @ _ => {
             let f = |t0: &DateTime<Utc>, t1: &DateTime<Utc>, win: WindowStats| {
                 println!("Window {:?} -- {:?}", t0, t1);
@@ -116,26 +431,41 @@ fn main() {
             };
 
             match mode {
-                Mode::Emotes { index, .. } => {
-                    let emote_index = chatan::emote_index::load_index(&index).expect("Could not load emote index");
-                    logs.slide_token_counts(start, end, step, size, f, |t| emote_index.contains_key(t))
-                },
-                Mode::Tokens => logs.slide_token_counts(start, end, step, size, f, |_| true),
+                Mode::Emotes { .. } => logs.slide_token_counts_incremental(
+                    start, end, step, size, f,
+                    &token_count_options(audience, counting, &*tokenizer).with_token_filter(|t: &str| emote_index.contains_key(t))
+                ),
+                Mode::Tokens { .. } => logs.slide_token_counts_incremental(
+                    start, end, step, size, f, &token_count_options(audience, counting, &*tokenizer)
+                ),
                 _ => unreachable!()
             }
         }
-    }.ok().expect("Failed to slide through the logs");
+    }.unwrap_or_else(|err| panic!("Failed to slide through the logs: {}", err));
 
-    let file = File::create(opt.output_file)
-        .expect("Could not create output file");
+    println!("Successfully rolled through logs in {:.3}s", t.elapsed().as_secs_f64());
+    result
+}
 
-    serde_json::to_writer(file, &result)
-        .expect("Could not write output file");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-//    let mut wrt = csv::Writer::from_writer(file);
-//    for el in &result {
-//        wrt.serialize(el).expect("Could not write output file");
-//    }
+    #[test]
+    fn test_parse_channels() {
+        let opt = RollingTop::from_iter_safe(&[
+            "rolling_top", "--top", "10", "--frequency-threshold", "1", "--output", "out.json",
+            "--channel", "a", "--channel", "b", "messages"
+        ])
+            .expect("Should be able to parse arguments");
+        assert_eq!(opt.channels, vec!["a", "b"]);
+        assert!(match opt.mode { Mode::Messages => true, _ => false });
+
+        // a directory is a single channel, so it can not be split per channel
+        assert!(RollingTop::from_iter_safe(&[
+            "rolling_top", "--top", "10", "--frequency-threshold", "1", "--output", "out.json",
+            "--directory", "logs", "--per-channel", "messages"
+        ]).is_err());
+    }
 
-    println!("Successfully rolled through logs in {:.3}s", t.elapsed().as_secs_f64());
 }
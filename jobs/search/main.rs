@@ -0,0 +1,421 @@
+extern crate chatan;
+extern crate structopt;
+use structopt::StructOpt;
+
+use chatan::overrustle::{OverRustleLogs, DataLoadMode, DEFAULT_BASE_URL};
+use chatan::chatlog::{ChatLog, DirectoryLog, MergedChatLog, Partition};
+use chatan::message::{Message, LogFormat};
+use chrono::{Utc, DateTime};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Search through chat logs")]
+struct Search {
+    /// Substring to look for in message text, or regular expression if `--regex` is given
+    #[structopt(name = "pattern")]
+    pattern: String,
+    #[structopt(name = "regex", long)]
+    regex: bool,
+    #[structopt(name = "ignore-case", short = "i", long)]
+    ignore_case: bool,
+    /// Only search messages of this user, can be given several times
+    #[structopt(name = "user", long, number_of_values = 1)]
+    users: Vec<String>,
+    #[structopt(name = "start", long)]
+    start: Option<DateTime<Utc>>,
+    #[structopt(name = "end", long)]
+    end: Option<DateTime<Utc>>,
+    /// Number of messages to show before each match
+    #[structopt(name = "before", short = "B", long, default_value = "0")]
+    before: usize,
+    /// Number of messages to show after each match
+    #[structopt(name = "after", short = "A", long, default_value = "0")]
+    after: usize,
+    /// One of `text`, `jsonl` or `csv`
+    #[structopt(name = "output-format", long, default_value = "text")]
+    output_format: OutputFormat,
+    /// Can be given several times, in which case logs of all the channels are searched
+    #[structopt(name = "channel", long, number_of_values = 1)]
+    channels: Vec<String>,
+    #[structopt(name = "data-load-mode", long, default_value = "local")]
+    data_load_mode: DataLoadMode,
+    #[structopt(name = "cache-dir", long)]
+    cache_dir: Option<PathBuf>,
+    /// Read logs from a local directory instead of OverRustle
+    #[structopt(name = "directory", long)]
+    directory: Option<PathBuf>,
+    #[structopt(name = "filename-pattern", long, default_value = "%Y-%m-%d.txt")]
+    filename_pattern: String,
+    #[structopt(name = "format", long, default_value = "overrustle")]
+    format: LogFormat,
+    #[structopt(name = "base-url", long)]
+    base_url: Option<String>,
+    /// Ignore persisted index of OverRustle logs and scrape all the months again
+    #[structopt(name = "full-resync", long)]
+    full_resync: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OutputFormat {
+    Text,
+    JsonLines,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(s.to_string())
+        }
+    }
+}
+
+enum Matcher {
+    Substring(String),
+    /// Pattern is lowercased already
+    SubstringIgnoreCase(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+
+    fn new(pattern: &str, regex: bool, ignore_case: bool) -> Matcher {
+        if regex {
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .expect("Invalid regular expression");
+            Matcher::Regex(regex)
+        } else if ignore_case {
+            Matcher::SubstringIgnoreCase(pattern.to_lowercase())
+        } else {
+            Matcher::Substring(pattern.to_string())
+        }
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Substring(s) => text.contains(s.as_str()),
+            Matcher::SubstringIgnoreCase(s) => text.to_lowercase().contains(s.as_str()),
+            Matcher::Regex(r) => r.is_match(text),
+        }
+    }
+
+}
+
+/// Message found by search, or message around it
+#[derive(Debug, Serialize)]
+struct Record {
+    timestamp: DateTime<Utc>,
+    channel: Option<String>,
+    user: String,
+    message: String,
+    is_context: bool,
+}
+
+impl Record {
+    fn new(message: &Message, is_context: bool) -> Record {
+        Record {
+            timestamp: message.timestamp(),
+            channel: message.channel().map(|c| c.to_string()),
+            user: message.user().to_string(),
+            message: message.message().to_string(),
+            is_context,
+        }
+    }
+}
+
+enum Printer<W: Write> {
+    Text(W),
+    JsonLines(W),
+    Csv(csv::Writer<W>),
+}
+
+impl<W: Write> Printer<W> {
+
+    fn new(format: OutputFormat, out: W) -> Printer<W> {
+        match format {
+            OutputFormat::Text => Printer::Text(out),
+            OutputFormat::JsonLines => Printer::JsonLines(out),
+            OutputFormat::Csv => Printer::Csv(csv::Writer::from_writer(out)),
+        }
+    }
+
+    fn print(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            Printer::Text(out) => {
+                if let Some(channel) = &record.channel {
+                    write!(out, "#{} ", channel)?;
+                }
+                writeln!(out, "[{}] {}: {}", record.timestamp.format("%Y-%m-%d %H:%M:%S UTC"), record.user, record.message)
+            },
+            Printer::JsonLines(out) => {
+                serde_json::to_writer(&mut *out, record)?;
+                writeln!(out)
+            },
+            Printer::Csv(out) => out.serialize(record).map_err(|err| io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+
+    /// Separates groups of messages which are not adjacent in the logs
+    fn print_separator(&mut self) -> io::Result<()> {
+        match self {
+            Printer::Text(out) => writeln!(out, "--"),
+            // records are marked with `is_context` instead
+            _ => Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Printer::Text(out) | Printer::JsonLines(out) => out.flush(),
+            Printer::Csv(out) => out.flush(),
+        }
+    }
+
+}
+
+/// Prints matching messages along with context around them, the same way grep does
+struct Searcher<W: Write> {
+    matcher: Matcher,
+    users: HashSet<String>,
+    before: usize,
+    after: usize,
+    printer: Printer<W>,
+    /// Number of messages seen so far
+    seq: u64,
+    last_printed: Option<u64>,
+    after_left: usize,
+    /// Messages which might be printed as context of the next match
+    before_buffer: VecDeque<(u64, Record)>,
+    n_matches: u64,
+}
+
+impl<W: Write> Searcher<W> {
+
+    /// Users are matched case-insensitively, empty set matches everyone
+    fn new(matcher: Matcher, users: &[String], before: usize, after: usize, printer: Printer<W>) -> Searcher<W> {
+        Searcher {
+            matcher,
+            users: users.iter().map(|u| u.to_lowercase()).collect(),
+            before,
+            after,
+            printer,
+            seq: 0,
+            last_printed: None,
+            after_left: 0,
+            before_buffer: VecDeque::new(),
+            n_matches: 0,
+        }
+    }
+
+    fn is_match(&self, message: &Message) -> bool {
+        (self.users.is_empty() || self.users.contains(&message.user().to_lowercase()))
+            && self.matcher.is_match(message.message())
+    }
+
+    fn print(&mut self, seq: u64, record: &Record) -> io::Result<()> {
+        let has_context = self.before > 0 || self.after > 0;
+        if has_context && self.last_printed.map_or(false, |last| last + 1 != seq) {
+            self.printer.print_separator()?;
+        }
+        self.last_printed = Some(seq);
+        self.printer.print(record)
+    }
+
+    fn process(&mut self, message: &Message) -> io::Result<()> {
+        self.seq += 1;
+        if self.is_match(message) {
+            self.n_matches += 1;
+            while let Some((seq, record)) = self.before_buffer.pop_front() {
+                self.print(seq, &record)?;
+            }
+            self.print(self.seq, &Record::new(message, false))?;
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            self.after_left -= 1;
+            self.print(self.seq, &Record::new(message, true))?;
+        } else if self.before > 0 {
+            self.before_buffer.push_back((self.seq, Record::new(message, true)));
+            if self.before_buffer.len() > self.before {
+                self.before_buffer.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+}
+
+/// Search messages sent between `start` and `end`, whole logs by default
+fn search<L, W>(
+    logs: &mut L, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>, searcher: &mut Searcher<W>
+) -> io::Result<()>
+    where
+        L: ChatLog,
+        L::Error: Display,
+        W: Write
+{
+    let (logs_start, logs_end) = match logs.span() {
+        Some(span) => span,
+        None => return Ok(())
+    };
+    let start = start.unwrap_or(logs_start);
+    let end = end.unwrap_or(logs_end);
+
+    for partition in logs.partitions() {
+        if partition.end() < start || end < partition.start() {
+            continue;
+        }
+        // days missing from the logs are not an error for search
+        let chunks = match logs.load_partition_stream(&partition) {
+            Ok(chunks) => chunks,
+            Err(_) => continue
+        };
+        for chunk in chunks {
+            // matches found before the error are already printed, so just report the rest as lost
+            let messages = match chunk {
+                Ok(messages) => messages,
+                Err(err) => {
+                    eprintln!("Could not read [{}; {}): {}", partition.start(), partition.end(), err);
+                    break;
+                }
+            };
+            for message in messages.temporal_slice(&start, &end) {
+                searcher.process(message)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let opt = Search::from_args();
+
+    let stdout = io::stdout();
+    let mut searcher = Searcher::new(
+        Matcher::new(&opt.pattern, opt.regex, opt.ignore_case),
+        &opt.users,
+        opt.before,
+        opt.after,
+        Printer::new(opt.output_format, stdout.lock()),
+    );
+
+    let result = match opt.directory.as_ref() {
+        Some(directory) => {
+            let mut logs = DirectoryLog::open(directory.clone(), &opt.filename_pattern, opt.format.line_parser())
+                .expect("Could not open log directory");
+            search(&mut logs, opt.start, opt.end, &mut searcher)
+        },
+        None => {
+            assert!(!opt.channels.is_empty(), "At least one --channel is required for OverRustle logs");
+            let cache_dir = opt.cache_dir.clone().expect("--cache-dir is required for OverRustle logs");
+
+            let mut sources = Vec::new();
+            for channel in opt.channels.iter() {
+                let mut logs = OverRustleLogs::new(cache_dir.clone(), channel.clone(), opt.data_load_mode)
+                    .with_base_url(opt.base_url.clone().unwrap_or_else(|| DEFAULT_BASE_URL.to_string()))
+                    .with_full_resync(opt.full_resync);
+                logs.sync().expect("Could not sync logs");
+                if let Some(err) = logs.manifest_error() {
+                    eprintln!("Could not load persisted index for {}, did full resync: {}", channel, err);
+                }
+                sources.push((channel.clone(), logs));
+            }
+
+            if sources.len() == 1 {
+                search(&mut sources.pop().unwrap().1, opt.start, opt.end, &mut searcher)
+            } else {
+                search(&mut MergedChatLog::new(sources), opt.start, opt.end, &mut searcher)
+            }
+        }
+    };
+
+    result
+        .and_then(|_| searcher.printer.flush())
+        .expect("Could not write search results");
+    eprintln!("{} matches found", searcher.n_matches);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chatan::message::overrustle::parse_string;
+
+    fn run<F: FnOnce(&mut Searcher<Vec<u8>>)>(matcher: Matcher, before: usize, after: usize, f: F) -> Vec<String> {
+        let mut searcher = Searcher::new(matcher, &[], before, after, Printer::new(OutputFormat::Text, Vec::new()));
+        f(&mut searcher);
+        match searcher.printer {
+            Printer::Text(out) => String::from_utf8(out).unwrap()
+                .lines()
+                // keep only user and message, or separator
+                .map(|l| l.splitn(2, "] ").last().unwrap().to_string())
+                .collect(),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_matcher() {
+        assert!(Matcher::new("Kappa", false, false).is_match("xKappax"));
+        assert!(!Matcher::new("kappa", false, false).is_match("Kappa"));
+        assert!(Matcher::new("kAPPA", false, true).is_match("Kappa"));
+        assert!(Matcher::new(r"^\w+ \d+$", true, false).is_match("Kappa 123"));
+        assert!(!Matcher::new(r"^kappa \d+$", true, false).is_match("Kappa 123"));
+        assert!(Matcher::new(r"^kappa \d+$", true, true).is_match("Kappa 123"));
+    }
+
+    #[test]
+    fn test_overlapping_context() {
+        let messages = parse_string(concat!(
+            "[2019-07-01 00:00:00 UTC] user1: a\n",
+            "[2019-07-01 00:00:01 UTC] user1: match\n",
+            "[2019-07-01 00:00:02 UTC] user1: b\n",
+            "[2019-07-01 00:00:03 UTC] user1: match\n",
+            "[2019-07-01 00:00:04 UTC] user1: c\n",
+            "[2019-07-01 00:00:05 UTC] user1: d\n",
+            "[2019-07-01 00:00:06 UTC] user1: e\n",
+            "[2019-07-01 00:00:07 UTC] user1: match\n",
+        ).to_string());
+        let lines = run(Matcher::new("match", false, false), 1, 1, |searcher| {
+            for message in messages.vec() {
+                searcher.process(message).unwrap();
+            }
+            assert_eq!(searcher.n_matches, 3);
+        });
+        // context shared by two matches is printed once, and only gaps are separated
+        assert_eq!(lines, vec![
+            "user1: a", "user1: match", "user1: b", "user1: match", "user1: c", "--", "user1: e", "user1: match",
+        ]);
+    }
+
+    #[test]
+    fn test_context_across_partitions() {
+        let path = std::env::temp_dir().join(format!("chatan-test-search-{}", std::process::id()));
+        std::fs::create_dir_all(&path).expect("Could not create test directory");
+        std::fs::write(path.join("2019-07-01.txt"), "[2019-07-01 23:59:58 UTC] user1: a\n[2019-07-01 23:59:59 UTC] user2: match\n")
+            .expect("Could not write test file");
+        std::fs::write(path.join("2019-07-02.txt"), "[2019-07-02 00:00:00 UTC] user3: b\n[2019-07-02 00:00:01 UTC] user4: c\n")
+            .expect("Could not write test file");
+        let mut logs = DirectoryLog::open(path.clone(), DirectoryLog::DEFAULT_PATTERN, LogFormat::OverRustle.line_parser())
+            .expect("Could not open directory");
+
+        let lines = run(Matcher::new("MATCH", false, true), 1, 2, |searcher| {
+            search(&mut logs, None, None, searcher).expect("Should be able to search");
+        });
+        assert_eq!(lines, vec!["user1: a", "user2: match", "user3: b", "user4: c"]);
+        std::fs::remove_dir_all(&path).expect("Could not remove test directory");
+    }
+
+}
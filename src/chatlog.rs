@@ -1,42 +1,652 @@
-use super::message::{Message, Messages};
+use super::message::{Message, Messages, MessageStream, LineParser};
 use super::util::day_after;
-use chrono::{Utc, DateTime, Date};
+use super::compression::Compression;
+use super::tokenizer::Tokenizer;
+use chrono::{Utc, DateTime, Date, Datelike, Timelike, TimeZone, NaiveDate};
 use counter::Counter;
+use std::collections::{HashMap, HashSet, BTreeMap, VecDeque};
+use std::ops::Range;
+use std::borrow::Cow;
+use std::rc::Rc;
+use rayon::prelude::*;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-const SECS_PER_DAY: i64 = 24 * 60 * 60;
-
-/// Represents an error occured when sliding through `DailyChatLog`
-pub enum SlideError {
+/// Reason sliding through a chat log could not be started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlideErrorKind {
+    /// Start is after end, or interval is not fully covered by the chat log
     InvalidTimeInterval,
+    /// Chat log is empty, or window is larger than the whole chat log
     NotEnoughData,
+    /// Partition covering [start; end) failed to load, and `ChatLog::on_load_error` asked to abort
+    LoadFailed { start: DateTime<Utc>, end: DateTime<Utc> },
+}
+
+/// What sliding through `ChatLog` should do about a partition which failed to load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadErrorAction {
+    /// Treat partition as empty. Partition is reported as missing by `slide_partial`
+    Skip,
+    /// Try to load partition again
+    Retry,
+    /// Stop sliding with `SlideErrorKind::LoadFailed`
+    Abort,
+}
+
+/// Represents an error occurred when sliding through `ChatLog`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlideError {
+    pub kind: SlideErrorKind,
+    /// Interval which was requested to slide through
+    pub requested: (DateTime<Utc>, DateTime<Utc>),
+    /// Window size, in seconds
+    pub size: u32,
+    /// Time span covered by the chat log, or None if it is empty
+    pub available: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Display for SlideError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let reason = match self.kind {
+            SlideErrorKind::InvalidTimeInterval => "interval is not covered by the chat log".to_string(),
+            SlideErrorKind::NotEnoughData => "not enough data".to_string(),
+            SlideErrorKind::LoadFailed { start, end } => format!("could not load [{}; {})", start, end),
+        };
+        write!(
+            f, "Cannot slide through [{}; {}] with {}s windows: {}", self.requested.0, self.requested.1, self.size, reason
+        )?;
+        match self.available {
+            Some((t0, t1)) => write!(f, " (chat log covers [{}; {}))", t0, t1),
+            None => write!(f, " (chat log is empty)")
+        }
+    }
+}
+
+impl std::error::Error for SlideError {}
+
+/// Parts of a window not covered by chat log data, reported by `slide_partial`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Time spans within the window for which there is no data, i.e. which are outside of the
+    /// chat log, fall between its partitions, or belong to partitions which failed to load.
+    /// For daily chat logs these are missing days.
+    pub missing: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Coverage {
+
+    /// Whether data for the whole window is available
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Compute coverage of [t0; t1) by time-ordered, non-overlapping spans
+    fn compute<I: Iterator<Item=(DateTime<Utc>, DateTime<Utc>)>>(t0: DateTime<Utc>, t1: DateTime<Utc>, covered: I) -> Coverage {
+        let mut missing = Vec::new();
+        let mut cur = t0;
+        for (start, end) in covered {
+            if end <= cur {
+                continue;
+            }
+            if start >= t1 {
+                break;
+            }
+            if start > cur {
+                missing.push((cur, start));
+            }
+            cur = end;
+        }
+        if cur < t1 {
+            missing.push((cur, t1));
+        }
+        Coverage { missing }
+    }
+
+}
+
+/// How token occurrences are counted in a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountingMode {
+    /// Every occurrence of a token is counted
+    Occurrences,
+
+    /// Token is counted at most once per message
+    Messages,
+
+    /// Token is counted at most once per user in a window, so counts reflect how many
+    /// users used a token rather than how many times it was spammed
+    Users,
+}
+
+impl Default for CountingMode {
+    fn default() -> Self {
+        CountingMode::Occurrences
+    }
+}
+
+impl FromStr for CountingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "occurrences" => Ok(CountingMode::Occurrences),
+            "messages" => Ok(CountingMode::Messages),
+            "users" => Ok(CountingMode::Users),
+            _ => Err(s.to_string())
+        }
+    }
+}
+
+impl ToString for CountingMode {
+    fn to_string(&self) -> String {
+        match self {
+            CountingMode::Occurrences => "Occurrences".to_string(),
+            CountingMode::Messages => "Messages".to_string(),
+            CountingMode::Users => "Users".to_string(),
+        }
+    }
+}
+
+/// What `window_stats` and `ChatLog::slide_token_counts*` count: messages satisfying
+/// `message_filter` are split into tokens by `tokenizer`, and tokens satisfying `token_filter`
+/// are counted according to `counting`. By default every token of every message is counted.
+#[derive(Clone)]
+pub struct TokenCountOptions<'t, Tok: ?Sized, MessageFilter = fn(&Message) -> bool, TokenFilter = fn(&str) -> bool> {
+    pub tokenizer: &'t Tok,
+    pub counting: CountingMode,
+    pub message_filter: MessageFilter,
+    pub token_filter: TokenFilter,
+}
+
+impl<'t, Tok: Tokenizer + ?Sized> TokenCountOptions<'t, Tok> {
+
+    pub fn new(tokenizer: &'t Tok) -> Self {
+        TokenCountOptions {
+            tokenizer,
+            counting: CountingMode::default(),
+            message_filter: |_| true,
+            token_filter: |_| true,
+        }
+    }
+
+}
+
+impl<'t, Tok, MessageFilter, TokenFilter> TokenCountOptions<'t, Tok, MessageFilter, TokenFilter>
+    where
+        Tok: Tokenizer + ?Sized,
+        MessageFilter: Fn(&Message) -> bool,
+        TokenFilter: Fn(&str) -> bool
+{
+
+    pub fn with_counting(self, counting: CountingMode) -> Self {
+        TokenCountOptions { counting, ..self }
+    }
+
+    /// Count only messages satisfying `message_filter`, e.g. only messages sent by subscribers
+    pub fn with_message_filter<F: Fn(&Message) -> bool>(self, message_filter: F) -> TokenCountOptions<'t, Tok, F, TokenFilter> {
+        let TokenCountOptions { tokenizer, counting, token_filter, .. } = self;
+        TokenCountOptions { tokenizer, counting, message_filter, token_filter }
+    }
+
+    /// Count only tokens satisfying `token_filter`, e.g. only emotes
+    pub fn with_token_filter<F: Fn(&str) -> bool>(self, token_filter: F) -> TokenCountOptions<'t, Tok, MessageFilter, F> {
+        let TokenCountOptions { tokenizer, counting, message_filter, .. } = self;
+        TokenCountOptions { tokenizer, counting, message_filter, token_filter }
+    }
+
+    /// Replace `tokens` with the tokens of `msg` satisfying `token_filter`, adding the number
+    /// of all of its tokens to `total`
+    fn filtered_tokens<'a>(&self, msg: &'a Message, tokens: &mut Vec<Cow<'a, str>>, total: &mut u64) {
+        tokens.clear();
+        let token_filter = &self.token_filter;
+        self.tokenizer.tokenize(msg.message(), &mut |tok| {
+            *total += 1;
+            if token_filter(&tok) {
+                tokens.push(tok);
+            }
+        });
+    }
+
 }
 
 /// Represents statistics computed over window of tokenized messages
 pub struct WindowStats<'a> {
     pub n_messages: u64,
+    /// Total number of tokens, regardless of counting mode
     pub n_tokens: u64,
+    /// Total number of tokens satisfying filter, regardless of counting mode
     pub n_tokens_filtered: u64,
-    pub token_counts: Counter<&'a str, u64>
+    pub token_counts: Counter<Cow<'a, str>, u64>
 }
 
-/// Represents daily chat log, i.e. chat log where data is stored in per-day files.
-/// TODO generalize to any time interval as a unit,
-/// also fetching one day at a time is probably the most effective approach for step <= 1d
-pub trait DailyChatLog {
+/// Compute statistics of tokens in window, see `TokenCountOptions`
+pub fn window_stats<'a, Tok, MessageFilter, TokenFilter>(
+    win: &mut dyn Iterator<Item=&'a Message>, options: &TokenCountOptions<Tok, MessageFilter, TokenFilter>
+) -> WindowStats<'a>
+    where
+        Tok: Tokenizer + ?Sized,
+        MessageFilter: Fn(&Message) -> bool,
+        TokenFilter: Fn(&str) -> bool
+{
+    let counting = options.counting;
+    let mut total: u64 = 0;
+    let mut total_filtered: u64 = 0;
+    let mut total_msgs: u64 = 0;
+    let mut counter: Counter<Cow<'a, str>, u64> = Counter::new();
+    let mut tokens = Vec::new();
+    let mut user_tokens = HashSet::new();
 
-    /// Range of dates in this chatlog, or None if it is empty
-    fn range(&self) -> Option<(Date<Utc>, Date<Utc>)>;
+    for msg in win.filter(|msg| (options.message_filter)(msg)) {
+        total_msgs += 1;
+        options.filtered_tokens(msg, &mut tokens, &mut total);
+        total_filtered += tokens.len() as u64;
+        if counting != CountingMode::Occurrences {
+            tokens.sort_unstable();
+            tokens.dedup();
+        }
+        for tok in tokens.drain(..) {
+            if counting == CountingMode::Users && !user_tokens.insert((msg.user(), tok.clone())) {
+                continue;
+            }
+            *counter.entry(tok).or_insert(0) += 1;
+        }
+    }
 
-    /// Load data for a given date.
-    fn load(&mut self, date: &Date<Utc>) -> Option<Messages>;
+    WindowStats {
+        n_messages: total_msgs,
+        n_tokens: total,
+        n_tokens_filtered: total_filtered,
+        token_counts: counter
+    }
+}
+
+/// Count n-grams in window, i.e. sequences of `n` consecutive tokens of the same message joined
+/// by a space, counting only messages satisfying `message_filter`. Unigrams borrow from messages.
+pub fn window_ngrams<'a, MessageFilter, Tok>(
+    win: &mut dyn Iterator<Item=&'a Message>, message_filter: &MessageFilter, tokenizer: &Tok, n: usize
+) -> Counter<Cow<'a, str>, u64>
+    where
+        MessageFilter: Fn(&Message) -> bool,
+        Tok: Tokenizer + ?Sized
+{
+    assert!(n > 0, "N-grams should have at least one token");
+    let mut counter: Counter<Cow<'a, str>, u64> = Counter::new();
+    let mut tokens = Vec::new();
+
+    for msg in win.filter(|msg| message_filter(msg)) {
+        tokens.clear();
+        tokenizer.tokenize(msg.message(), &mut |tok| tokens.push(tok));
+        for ngram in tokens.windows(n) {
+            let key = if n == 1 { ngram[0].clone() } else { Cow::Owned(ngram.join(" ")) };
+            *counter.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    counter
+}
+
+/// Detect phrases repeated in window, e.g. copypastas. Phrase is a whole message of at least
+/// `min_tokens` tokens, normalised by joining its tokens with a space, and is counted once per
+/// user, so a single spammer can't make a phrase popular.
+pub fn window_phrases<'a, MessageFilter, Tok>(
+    win: &mut dyn Iterator<Item=&'a Message>, message_filter: &MessageFilter, tokenizer: &Tok, min_tokens: usize
+) -> Counter<Cow<'a, str>, u64>
+    where
+        MessageFilter: Fn(&Message) -> bool,
+        Tok: Tokenizer + ?Sized
+{
+    let mut counter: Counter<Cow<'a, str>, u64> = Counter::new();
+    let mut seen = HashSet::new();
+
+    for msg in win.filter(|msg| message_filter(msg)) {
+        let tokens = tokenizer.tokens(msg.message());
+        if tokens.is_empty() || tokens.len() < min_tokens {
+            continue;
+        }
+        let phrase = tokens.join(" ");
+        if seen.insert((msg.user(), phrase.clone())) {
+            *counter.entry(Cow::Owned(phrase)).or_insert(0) += 1;
+        }
+    }
+
+    counter
+}
+
+/// Activity of a single chatter within a window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChatterStats {
+    pub n_messages: u64,
+    pub n_tokens: u64,
+}
+
+/// Represents statistics of chatters in window
+pub struct UserWindowStats<'a> {
+    pub n_messages: u64,
+    pub chatters: HashMap<&'a str, ChatterStats>,
+    /// Chatters who were not seen in any of the earlier windows
+    pub n_new_chatters: u64,
+    pub n_returning_chatters: u64,
+}
+
+impl<'a> UserWindowStats<'a> {
+
+    pub fn n_chatters(&self) -> u64 {
+        self.chatters.len() as u64
+    }
+
+    /// Chatters who sent most messages, in descending order of messages sent
+    pub fn top_chatters(&self, n: usize) -> Vec<(&'a str, ChatterStats)> {
+        let mut chatters = self.chatters.iter().map(|(u, s)| (*u, *s)).collect::<Vec<_>>();
+        chatters.sort_unstable_by(|l, r| r.1.n_messages.cmp(&l.1.n_messages).then(l.0.cmp(r.0)));
+        chatters.truncate(n);
+        chatters
+    }
+
+    /// Number of chatters by number of messages they sent
+    pub fn messages_per_chatter(&self) -> BTreeMap<u64, u64> {
+        let mut distribution = BTreeMap::new();
+        for stats in self.chatters.values() {
+            *distribution.entry(stats.n_messages).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+}
+
+/// Compute statistics of chatters in window, counting only messages satisfying `message_filter`.
+/// `seen` holds chatters of earlier windows, chatters of this window are added to it.
+pub fn window_user_stats<'a, MessageFilter, Tok>(
+    win: &mut dyn Iterator<Item=&'a Message>, message_filter: &MessageFilter, tokenizer: &Tok,
+    seen: &mut HashSet<String>
+) -> UserWindowStats<'a>
+    where
+        MessageFilter: Fn(&Message) -> bool,
+        Tok: Tokenizer + ?Sized
+{
+    let mut n_messages = 0;
+    let mut chatters: HashMap<&'a str, ChatterStats> = HashMap::new();
+
+    for msg in win.filter(|msg| message_filter(msg)) {
+        n_messages += 1;
+        let stats = chatters.entry(msg.user()).or_default();
+        stats.n_messages += 1;
+        tokenizer.tokenize(msg.message(), &mut |_| stats.n_tokens += 1);
+    }
+
+    let mut n_new_chatters = 0;
+    for user in chatters.keys() {
+        if !seen.contains(*user) {
+            seen.insert(user.to_string());
+            n_new_chatters += 1;
+        }
+    }
+
+    UserWindowStats {
+        n_messages,
+        n_new_chatters,
+        n_returning_chatters: chatters.len() as u64 - n_new_chatters,
+        chatters
+    }
+}
+
+/// Maps strings to ids, so that each distinct string is only allocated once
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<Rc<str>, u32>,
+    strings: Vec<Rc<str>>,
+}
+
+impl Interner {
+
+    fn intern(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        let s: Rc<str> = Rc::from(s);
+        self.ids.insert(s.clone(), id);
+        self.strings.push(s);
+        id
+    }
+
+    fn get(&self, s: &str) -> Option<u32> {
+        self.ids.get(s).cloned()
+    }
+
+    fn resolve(&self, id: u32) -> &str {
+        &self.strings[id as usize]
+    }
+
+}
+
+/// Token counts of a window which are updated as messages enter and leave it. Tokens and
+/// users are interned, so only the first occurrence of each of them during the whole slide
+/// allocates.
+#[derive(Default)]
+struct RunningCounts {
+    counting: CountingMode,
+    n_messages: u64,
+    n_tokens: u64,
+    n_tokens_filtered: u64,
+    tokens: Interner,
+    users: Interner,
+    token_counts: HashMap<u32, u64>,
+    /// Occurrences of tokens by user, only maintained when counting once per user
+    user_tokens: HashMap<(u32, u32), u64>,
+}
+
+impl RunningCounts {
+
+    fn new(counting: CountingMode) -> RunningCounts {
+        RunningCounts { counting, ..Default::default() }
+    }
+
+    /// Add messages to counts, or subtract them if `add` is false
+    fn update<Tok, MessageFilter, TokenFilter>(
+        &mut self, messages: &[Message], options: &TokenCountOptions<Tok, MessageFilter, TokenFilter>, add: bool
+    )
+        where
+            Tok: Tokenizer + ?Sized,
+            MessageFilter: Fn(&Message) -> bool,
+            TokenFilter: Fn(&str) -> bool
+    {
+        let mut tokens = Vec::new();
+        for msg in messages.iter().filter(|msg| (options.message_filter)(msg)) {
+            let mut total = 0;
+            options.filtered_tokens(msg, &mut tokens, &mut total);
+            if add {
+                self.n_messages += 1;
+                self.n_tokens += total;
+                self.n_tokens_filtered += tokens.len() as u64;
+            } else {
+                self.n_messages -= 1;
+                self.n_tokens -= total;
+                self.n_tokens_filtered -= tokens.len() as u64;
+            }
+            if self.counting != CountingMode::Occurrences {
+                tokens.sort_unstable();
+                tokens.dedup();
+            }
+            for tok in tokens.drain(..) {
+                // subtracted tokens have been added before, so they are already interned
+                let tok = if add { self.tokens.intern(&tok) } else { self.tokens.get(&tok).unwrap() };
+                if self.counting == CountingMode::Users && !self.update_user_token(msg.user(), tok, add) {
+                    continue;
+                }
+                if add {
+                    *self.token_counts.entry(tok).or_insert(0) += 1;
+                } else {
+                    let count = self.token_counts.get_mut(&tok).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        self.token_counts.remove(&tok);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Update occurrences of token by user, returns true if user started or stopped using it
+    fn update_user_token(&mut self, user: &str, tok: u32, add: bool) -> bool {
+        let key = (if add { self.users.intern(user) } else { self.users.get(user).unwrap() }, tok);
+        if add {
+            let count = self.user_tokens.entry(key).or_insert(0);
+            *count += 1;
+            *count == 1
+        } else {
+            let count = self.user_tokens.get_mut(&key).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.user_tokens.remove(&key);
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    fn stats(&self) -> WindowStats {
+        let mut token_counts = Counter::new();
+        token_counts.extend(self.token_counts.iter().map(|(&tok, count)| (Cow::Borrowed(self.tokens.resolve(tok)), *count)));
+        WindowStats {
+            n_messages: self.n_messages,
+            n_tokens: self.n_tokens,
+            n_tokens_filtered: self.n_tokens_filtered,
+            token_counts
+        }
+    }
+
+}
+
+/// Parts of range `a` which are not in range `b`
+fn range_difference(a: &Range<usize>, b: &Range<usize>) -> [Range<usize>; 2] {
+    [
+        a.start..std::cmp::max(a.start, std::cmp::min(a.end, b.start)),
+        std::cmp::min(a.end, std::cmp::max(a.start, b.end))..a.end,
+    ]
+}
+
+/// Unit of storage of a chat log, e.g. a single day, hour or stream, covering [start; end) time span
+pub trait Partition: Clone + Ord + Send + Sync {
+
+    /// Start of the time span, inclusive
+    fn start(&self) -> DateTime<Utc>;
+
+    /// End of the time span, exclusive
+    fn end(&self) -> DateTime<Utc>;
+
+}
+
+/// Days are partitions of `DailyChatLog`
+impl Partition for Date<Utc> {
+
+    fn start(&self) -> DateTime<Utc> {
+        self.and_hms(0, 0, 0)
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        day_after(*self).and_hms(0, 0, 0)
+    }
+
+}
+
+/// Single hour, e.g. for logs rotated hourly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hour(DateTime<Utc>);
+
+impl Hour {
+
+    /// Hour `t` belongs to
+    pub fn containing(t: &DateTime<Utc>) -> Hour {
+        Hour(Utc.ymd(t.year(), t.month(), t.day()).and_hms(t.hour(), 0, 0))
+    }
+
+    pub fn next(&self) -> Hour {
+        Hour(self.0 + chrono::Duration::hours(1))
+    }
+
+}
+
+impl Partition for Hour {
+
+    fn start(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        self.0 + chrono::Duration::hours(1)
+    }
+
+}
+
+/// Arbitrary time span, e.g. for logs stored in one file per stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeSpan {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Partition for TimeSpan {
+
+    fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+}
+
+/// Number of windows `par_slide` evaluates at once per thread
+const PAR_SLIDE_WINDOWS_PER_THREAD: usize = 4;
+
+/// Stream of time-ordered chunks of a single partition, see `ChatLog::load_partition_stream`
+pub type Chunks<E> = Box<dyn Iterator<Item=Result<Messages, E>>>;
+
+/// Represents chat log stored in partitions, each of which can be loaded separately.
+pub trait ChatLog {
+
+    type Partition: Partition;
+
+    /// Error occurred when loading data
+    type Error: 'static;
+
+    /// Partitions of this chatlog ordered by time. Partitions must not overlap, but there can
+    /// be gaps between them.
+    fn partitions(&self) -> Vec<Self::Partition>;
+
+    /// Load data for a given partition.
+    fn load_partition(&mut self, partition: &Self::Partition) -> Result<Messages, Self::Error>;
+
+    /// Load data for a given partition as a stream of time-ordered chunks. Loading may also fail
+    /// mid-stream, in which case the error is yielded in place of a chunk. Default
+    /// implementation yields the whole partition as a single chunk.
+    fn load_partition_stream(&mut self, partition: &Self::Partition) -> Result<Chunks<Self::Error>, Self::Error> {
+        Ok(Box::new(Some(Ok(self.load_partition(partition)?)).into_iter()))
+    }
+
+    /// Time span covered by this chatlog, or None if it is empty
+    fn span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        partitions_range(&self.partitions())
+    }
+
+    /// Decide what `slide*` methods should do about a partition which failed to load, `attempt`
+    /// being the number of retries made so far. Default implementation skips the partition, as
+    /// chat logs often have gaps. Use `ErrorHandlingLog` to make this decision from outside.
+    fn on_load_error(&mut self, _partition: &Self::Partition, _error: &Self::Error, _attempt: u32) -> LoadErrorAction {
+        LoadErrorAction::Skip
+    }
 
     /// Iterate over the time interval within the index, using given step, sliding
     /// window of given size and window function F.
     ///
-    /// Returns an `SlideStatus::InvalidTimeInterval` if start or end time is outside of the index,
-    /// if start > end or if window size > index size.
-    ///
-    /// Returns `SlideStatus::NotEnoughDataInIndex` if index is empty
+    /// Returns `SlideErrorKind::InvalidTimeInterval` if start or end time is outside of the index
+    /// or if start > end, and `SlideErrorKind::NotEnoughData` if index is empty or if window
+    /// size > index size. Use `slide_partial` to slide through intervals not fully covered by the index.
     fn slide<F>(
         &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
         window_fn: F
@@ -47,58 +657,74 @@ pub trait DailyChatLog {
         // avoid putting mut into function signature
         let mut f = window_fn;
 
-        let index_size = match self.range() {
-            Some((t0, t1)) => day_after(t1) - t0,
-            None => {
-                return Err(SlideError::NotEnoughData);
-            }
-        };
-        let size = chrono::Duration::seconds(size as i64);
-        let step = chrono::Duration::seconds(step as i64);
+        check_interval(self.span(), &start, &end, size)?;
+        self.slide_partial(start, end, step, size, |t0, t1, _, win| f(t0, t1, win))
+    }
 
-        if start > end || (end - start) > index_size {
-            return Err(SlideError::InvalidTimeInterval);
-        }
+    /// Same as `slide`, but the interval doesn't have to be covered by the index. Window function
+    /// additionally receives `Coverage` of each window, telling which parts of it have no data.
+    ///
+    /// Returns `SlideErrorKind::InvalidTimeInterval` if start > end, and
+    /// `SlideErrorKind::NotEnoughData` if index is empty.
+    fn slide_partial<F>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        window_fn: F
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &Coverage, &mut dyn Iterator<Item=&Message>) -> ()
+    {
+        let mut f = window_fn;
 
-        if size > index_size {
-            return Err(SlideError::NotEnoughData);
+        let partitions = self.partitions();
+        let error = |kind| SlideError { kind, requested: (start, end), size, available: partitions_range(&partitions) };
+        if partitions.is_empty() {
+            return Err(error(SlideErrorKind::NotEnoughData));
+        }
+        if start > end {
+            return Err(error(SlideErrorKind::InvalidTimeInterval));
         }
+        let size = chrono::Duration::seconds(size as i64);
+        let step = chrono::Duration::seconds(step as i64);
 
         let mut cur = start;
-
-        // can be made more generic
-        let n_units = size.num_seconds() / SECS_PER_DAY + if size.num_seconds() % SECS_PER_DAY == 0 { 0 } else { 1 };
-        let mut loaded_files: Vec<(Date<Utc>, Messages)> = Vec::with_capacity((n_units * 2) as usize);
+        let mut next_partition = 0;
+        // partitions which failed to load are kept as empty, but reported as missing
+        let mut loaded_partitions: VecDeque<(Self::Partition, Messages, bool)> = VecDeque::new();
 
         while cur + size <= end {
             let cur_start = cur;
             let cur_end = cur + size;
-            let cur_date = cur.date();
 
-            // unload files that are no longer needed
-            loaded_files.drain_filter(|e| e.0 < cur_date);
+            // unload partitions that are no longer needed
+            while loaded_partitions.front().map_or(false, |(p, _, _)| p.end() <= cur_start) {
+                loaded_partitions.pop_front();
+            }
 
-            let mut date = match loaded_files.last() {
-                Some(v) => {
-                    // files up to this date were loaded.
-                    day_after(v.0)
-                },
-                None => {
-                    // no files loaded yet
-                    cur_date
-                }
-            };
+            // skip partitions stepped over entirely
+            while next_partition < partitions.len() && partitions[next_partition].end() <= cur_start {
+                next_partition += 1;
+            }
 
-            while date < (cur + size + step).date() {
-                loaded_files.push((date, self.load(&date).unwrap_or_else(|| Messages::empty())));
-                date = day_after(date);
+            while next_partition < partitions.len() && partitions[next_partition].start() <= cur_end {
+                let partition = &partitions[next_partition];
+                let (messages, loaded) = match load_or_handle(self, partition, Self::load_partition).map_err(error)? {
+                    Some(messages) => (messages, true),
+                    None => (Messages::empty(), false)
+                };
+                loaded_partitions.push_back((partition.clone(), messages, loaded));
+                next_partition += 1;
             }
 
-            let mut window = loaded_files
+            let coverage = Coverage::compute(cur_start, cur_end, loaded_partitions
                 .iter()
-                .flat_map(|(_, msgs)| msgs.temporal_slice(&cur_start, &cur_end).iter());
+                .filter(|(_, _, loaded)| *loaded)
+                .map(|(p, _, _)| (p.start(), p.end())));
 
-            f(&cur_start, &cur_end, &mut window);
+            let mut window = loaded_partitions
+                .iter()
+                .flat_map(|(_, msgs, _)| msgs.temporal_slice(&cur_start, &cur_end).iter());
+
+            f(&cur_start, &cur_end, &coverage, &mut window);
 
             cur = cur + step;
         }
@@ -106,43 +732,1308 @@ pub trait DailyChatLog {
         Ok(())
     }
 
-    fn slide_token_counts<F, Filter>(
-        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
-        f: F, filter: Filter
+    /// Split messages within [start; end] into consecutive windows, starting a new window before
+    /// message `next` whenever `is_boundary(previous, next, current_window_len)` returns true.
+    /// Bounds passed to `window_fn` are timestamps of the first and the last message of a window,
+    /// so windows are never empty.
+    ///
+    /// Returns `SlideErrorKind::InvalidTimeInterval` if start > end, and
+    /// `SlideErrorKind::NotEnoughData` if index is empty.
+    fn slide_segments<B, F>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, is_boundary: B, window_fn: F
     ) -> Result<(), SlideError>
         where
-            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, WindowStats) -> (),
-            Filter: Fn(&str) -> bool
+            B: FnMut(&Message, &Message, usize) -> bool,
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> ()
     {
-        let mut f = f;
-        self.slide(start, end, step, size, |t0, t1, win| {
-            let mut total: u64 = 0;
-            let mut total_filtered: u64 = 0;
-            let mut total_msgs: u64 = 0;
-
-            let counter: Counter<&str, u64> = win
-                .flat_map(|msg| {
-                    total_msgs += 1;
-                    msg.message().split_ascii_whitespace()
-                })
-                .filter(|tok| {
-                    total += 1;
-                    if filter(tok) {
-                        total_filtered += 1;
-                        true
-                    } else {
-                        false
+        let mut is_boundary = is_boundary;
+        let mut f = window_fn;
+
+        let partitions = self.partitions();
+        let error = |kind| SlideError { kind, requested: (start, end), size: 0, available: partitions_range(&partitions) };
+        if partitions.is_empty() {
+            return Err(error(SlideErrorKind::NotEnoughData));
+        }
+        if start > end {
+            return Err(error(SlideErrorKind::InvalidTimeInterval));
+        }
+
+        // messages of partitions overlapping current window, with ranges within [start; end]
+        let mut loaded_partitions: VecDeque<(Messages, Range<usize>)> = VecDeque::new();
+        // positions of the first message of current window and of the previous message,
+        // as (index of loaded partition, index of message)
+        let mut window_start = (0, 0);
+        let mut prev: Option<(usize, usize)> = None;
+        let mut window_len = 0;
+
+        for partition in partitions.iter().filter(|p| p.end() > start && p.start() <= end) {
+            // partitions which were skipped are treated as empty
+            let messages = match load_or_handle(self, partition, Self::load_partition).map_err(error)? {
+                Some(messages) => messages,
+                None => continue
+            };
+            let range = messages.temporal_range(&start, &end);
+            if range.start == range.end {
+                continue;
+            }
+            loaded_partitions.push_back((messages, range.clone()));
+            let mut current = loaded_partitions.len() - 1;
+
+            for idx in range {
+                if let Some(p) = prev {
+                    let boundary = is_boundary(
+                        &loaded_partitions[p.0].0.vec()[p.1],
+                        &loaded_partitions[current].0.vec()[idx],
+                        window_len
+                    );
+                    if boundary {
+                        emit_segment(&loaded_partitions, window_start, (current, idx), &mut f);
+                        // only partitions of the new window are needed from now on
+                        loaded_partitions.drain(..current);
+                        current = 0;
+                        window_start = (current, idx);
+                        window_len = 0;
                     }
-                })
-                .collect();
-
-            f(t0, t1, WindowStats {
-                n_messages: total_msgs,
-                n_tokens: total,
-                n_tokens_filtered: total_filtered,
-                token_counts: counter
-            });
-        })
+                } else {
+                    window_start = (current, idx);
+                }
+                prev = Some((current, idx));
+                window_len += 1;
+            }
+        }
+
+        if window_len > 0 {
+            let last = loaded_partitions.len() - 1;
+            emit_segment(&loaded_partitions, window_start, (last, loaded_partitions[last].1.end), &mut f);
+        }
+
+        Ok(())
+    }
+
+    /// Iterate over chat sessions (e.g. streams) within [start; end], i.e. periods of activity
+    /// separated by gaps of more than `max_gap` seconds without messages. See `slide_segments`.
+    fn slide_sessions<F>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, max_gap: u32, window_fn: F
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> ()
+    {
+        let max_gap = chrono::Duration::seconds(max_gap as i64);
+        self.slide_segments(start, end, |prev, next, _| next.timestamp() - prev.timestamp() > max_gap, window_fn)
     }
 
-}
\ No newline at end of file
+    /// Iterate over consecutive windows of `count` messages within [start; end]. The last window
+    /// can contain fewer messages. See `slide_segments`.
+    fn slide_message_count<F>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, count: usize, window_fn: F
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> ()
+    {
+        self.slide_segments(start, end, |_, _, len| len >= count, window_fn)
+    }
+
+    /// Same as `slide`, but data is consumed via `load_stream`, so only chunks overlapping
+    /// current window are kept in memory instead of whole days. Requires messages to be
+    /// ordered by time across chunks. If a partition fails mid-stream, `on_load_error` is
+    /// consulted with `attempt` 0: `Skip` drops the rest of the partition, while `Retry` and
+    /// `Abort` both stop sliding, as a partially consumed stream can not be retried.
+    fn slide_stream<F>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        window_fn: F
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> ()
+    {
+        let mut f = window_fn;
+
+        let partitions = self.partitions();
+        check_interval(partitions_range(&partitions), &start, &end, size)?;
+        let error = |kind| SlideError { kind, requested: (start, end), size, available: partitions_range(&partitions) };
+        let size = chrono::Duration::seconds(size as i64);
+        let step = chrono::Duration::seconds(step as i64);
+
+        let mut cur = start;
+        let mut next_partition = partitions.iter().take_while(|p| p.end() <= start).count();
+        let mut stream: Option<Chunks<Self::Error>> = None;
+        let mut loaded_chunks: VecDeque<Messages> = VecDeque::new();
+
+        while cur + size <= end {
+            let cur_start = cur;
+            let cur_end = cur + size;
+
+            // unload chunks that are no longer needed
+            while loaded_chunks.front().map_or(false, |c| c.vec().last().unwrap().timestamp() < cur_start) {
+                loaded_chunks.pop_front();
+            }
+
+            // load chunks until current window is covered
+            while loaded_chunks.back().map_or(true, |c| c.vec().last().unwrap().timestamp() < cur_end) {
+                let chunk = match stream.as_mut().and_then(|s| s.next()) {
+                    Some(Ok(chunk)) => chunk,
+                    Some(Err(err)) => {
+                        let partition = &partitions[next_partition - 1];
+                        match self.on_load_error(partition, &err, 0) {
+                            LoadErrorAction::Skip => stream = None,
+                            LoadErrorAction::Retry | LoadErrorAction::Abort => return Err(error(
+                                SlideErrorKind::LoadFailed { start: partition.start(), end: partition.end() }
+                            )),
+                        }
+                        continue;
+                    },
+                    None if next_partition < partitions.len() && partitions[next_partition].start() <= cur_end => {
+                        stream = load_or_handle(self, &partitions[next_partition], Self::load_partition_stream).map_err(error)?;
+                        next_partition += 1;
+                        continue;
+                    },
+                    None => break
+                };
+                if !chunk.vec().is_empty() {
+                    loaded_chunks.push_back(chunk);
+                }
+            }
+
+            let mut window = loaded_chunks
+                .iter()
+                .flat_map(|msgs| msgs.temporal_slice(&cur_start, &cur_end).iter());
+
+            f(&cur_start, &cur_end, &mut window);
+
+            cur = cur + step;
+        }
+
+        Ok(())
+    }
+
+    /// Slide through the logs, passing statistics of tokens in each window to `f`, see
+    /// `TokenCountOptions` for what is counted
+    fn slide_token_counts<F, Tok, MessageFilter, TokenFilter>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        f: F, options: &TokenCountOptions<Tok, MessageFilter, TokenFilter>
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, WindowStats) -> (),
+            Tok: Tokenizer + ?Sized,
+            MessageFilter: Fn(&Message) -> bool,
+            TokenFilter: Fn(&str) -> bool
+    {
+        let mut f = f;
+        self.slide(start, end, step, size, |t0, t1, win| {
+            f(t0, t1, window_stats(win, options));
+        })
+    }
+
+    /// Slide through the logs, passing counts of `n`-grams in each window to `f`, see
+    /// `window_ngrams`
+    fn slide_ngrams<F, MessageFilter, Tok>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        f: F, message_filter: MessageFilter, tokenizer: &Tok, n: usize
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, Counter<Cow<str>, u64>) -> (),
+            MessageFilter: Fn(&Message) -> bool,
+            Tok: Tokenizer + ?Sized
+    {
+        let mut f = f;
+        self.slide(start, end, step, size, |t0, t1, win| {
+            f(t0, t1, window_ngrams(win, &message_filter, tokenizer, n));
+        })
+    }
+
+    /// Slide through the logs, passing counts of repeated phrases in each window to `f`, see
+    /// `window_phrases`
+    fn slide_phrases<F, MessageFilter, Tok>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        f: F, message_filter: MessageFilter, tokenizer: &Tok, min_tokens: usize
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, Counter<Cow<str>, u64>) -> (),
+            MessageFilter: Fn(&Message) -> bool,
+            Tok: Tokenizer + ?Sized
+    {
+        let mut f = f;
+        self.slide(start, end, step, size, |t0, t1, win| {
+            f(t0, t1, window_phrases(win, &message_filter, tokenizer, min_tokens));
+        })
+    }
+
+    /// Slide through the logs, passing statistics of chatters in each window to `f`. Chatters
+    /// are new if they did not chat in any of the earlier windows, so with overlapping windows
+    /// the ones who chatted in the overlap are returning.
+    fn slide_user_stats<F, MessageFilter, Tok>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        f: F, message_filter: MessageFilter, tokenizer: &Tok
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, UserWindowStats) -> (),
+            MessageFilter: Fn(&Message) -> bool,
+            Tok: Tokenizer + ?Sized
+    {
+        let mut f = f;
+        let mut seen = HashSet::new();
+        self.slide(start, end, step, size, |t0, t1, win| {
+            f(t0, t1, window_user_stats(win, &message_filter, tokenizer, &mut seen));
+        })
+    }
+
+    /// Same as `slide`, but windows are evaluated in parallel, and results of `window_fn` are
+    /// returned in window order. Windows are processed in batches, so only partitions
+    /// overlapping the current batch are kept in memory.
+    fn par_slide<F, R>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        window_fn: F
+    ) -> Result<Vec<R>, SlideError>
+        where
+            F: Fn(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> R + Sync,
+            R: Send
+    {
+        let partitions = self.partitions();
+        check_interval(partitions_range(&partitions), &start, &end, size)?;
+        let error = |kind| SlideError { kind, requested: (start, end), size, available: partitions_range(&partitions) };
+        let size = chrono::Duration::seconds(size as i64);
+        let step = chrono::Duration::seconds(step as i64);
+        let batch_size = rayon::current_num_threads() * PAR_SLIDE_WINDOWS_PER_THREAD;
+
+        let mut results = Vec::new();
+        let mut cur = start;
+        let mut next_partition = 0;
+        let mut loaded_partitions: VecDeque<(Self::Partition, Messages)> = VecDeque::new();
+
+        while cur + size <= end {
+            let mut batch = Vec::with_capacity(batch_size);
+            while batch.len() < batch_size && cur + size <= end {
+                batch.push((cur, cur + size));
+                cur = cur + step;
+            }
+            let batch_start = batch.first().unwrap().0;
+            let batch_end = batch.last().unwrap().1;
+
+            // unload partitions that are no longer needed
+            while loaded_partitions.front().map_or(false, |(p, _)| p.end() <= batch_start) {
+                loaded_partitions.pop_front();
+            }
+
+            // skip partitions stepped over entirely
+            while next_partition < partitions.len() && partitions[next_partition].end() <= batch_start {
+                next_partition += 1;
+            }
+
+            while next_partition < partitions.len() && partitions[next_partition].start() <= batch_end {
+                let partition = &partitions[next_partition];
+                // partitions which were skipped are treated as empty
+                let messages = load_or_handle(self, partition, Self::load_partition).map_err(error)?
+                    .unwrap_or_else(Messages::empty);
+                loaded_partitions.push_back((partition.clone(), messages));
+                next_partition += 1;
+            }
+
+            let loaded_partitions = &loaded_partitions;
+            let window_fn = &window_fn;
+            results.par_extend(batch.par_iter().map(|(t0, t1)| {
+                let mut window = loaded_partitions
+                    .iter()
+                    .flat_map(|(_, msgs)| msgs.temporal_slice(t0, t1).iter());
+                window_fn(t0, t1, &mut window)
+            }));
+        }
+
+        Ok(results)
+    }
+
+    /// Same as `slide_token_counts`, but instead of counting every window from scratch,
+    /// running counts are maintained: only messages entering the window are added and only
+    /// those leaving it are subtracted. Much faster when `step` is small compared to `size`.
+    fn slide_token_counts_incremental<F, Tok, MessageFilter, TokenFilter>(
+        &mut self, start: DateTime<Utc>, end: DateTime<Utc>, step: u32, size: u32,
+        f: F, options: &TokenCountOptions<Tok, MessageFilter, TokenFilter>
+    ) -> Result<(), SlideError>
+        where
+            F: FnMut(&DateTime<Utc>, &DateTime<Utc>, WindowStats) -> (),
+            Tok: Tokenizer + ?Sized,
+            MessageFilter: Fn(&Message) -> bool,
+            TokenFilter: Fn(&str) -> bool
+    {
+        let mut f = f;
+
+        let partitions = self.partitions();
+        check_interval(partitions_range(&partitions), &start, &end, size)?;
+        let error = |kind| SlideError { kind, requested: (start, end), size, available: partitions_range(&partitions) };
+        let size = chrono::Duration::seconds(size as i64);
+        let step = chrono::Duration::seconds(step as i64);
+
+        let mut cur = start;
+        let mut next_partition = 0;
+        // each partition remembers range of its messages in the previous window
+        let mut loaded_partitions: VecDeque<(Self::Partition, Messages, Range<usize>)> = VecDeque::new();
+        let mut counts = RunningCounts::new(options.counting);
+
+        while cur + size <= end {
+            let cur_start = cur;
+            let cur_end = cur + size;
+
+            // unload partitions that are no longer needed
+            while loaded_partitions.front().map_or(false, |(p, _, _)| p.end() <= cur_start) {
+                let (_, msgs, prev) = loaded_partitions.pop_front().unwrap();
+                counts.update(&msgs.vec()[prev], options, false);
+            }
+
+            // skip partitions stepped over entirely
+            while next_partition < partitions.len() && partitions[next_partition].end() <= cur_start {
+                next_partition += 1;
+            }
+
+            while next_partition < partitions.len() && partitions[next_partition].start() <= cur_end {
+                let partition = &partitions[next_partition];
+                // partitions which were skipped are treated as empty
+                let messages = load_or_handle(self, partition, Self::load_partition).map_err(error)?
+                    .unwrap_or_else(Messages::empty);
+                loaded_partitions.push_back((partition.clone(), messages, 0..0));
+                next_partition += 1;
+            }
+
+            for (_, msgs, prev) in loaded_partitions.iter_mut() {
+                let range = msgs.temporal_range(&cur_start, &cur_end);
+                for leaving in range_difference(prev, &range).iter() {
+                    counts.update(&msgs.vec()[leaving.clone()], options, false);
+                }
+                for entering in range_difference(&range, prev).iter() {
+                    counts.update(&msgs.vec()[entering.clone()], options, true);
+                }
+                *prev = range;
+            }
+
+            f(&cur_start, &cur_end, counts.stats());
+
+            cur = cur + step;
+        }
+
+        Ok(())
+    }
+
+}
+
+/// Represents daily chat log, i.e. chat log where data is stored in per-day files.
+/// Fetching one day at a time is probably the most effective approach for step <= 1d.
+pub trait DailyChatLog {
+
+    /// Range of dates in this chatlog, or None if it is empty
+    fn range(&self) -> Option<(Date<Utc>, Date<Utc>)>;
+
+    /// Error occurred when loading data
+    type Error: 'static;
+
+    /// Load data for a given date.
+    fn load(&mut self, date: &Date<Utc>) -> Result<Messages, Self::Error>;
+
+    /// Load data for a given date as a stream of time-ordered chunks, see
+    /// `ChatLog::load_partition_stream`. Default implementation yields the whole day as a single chunk.
+    fn load_stream(&mut self, date: &Date<Utc>) -> Result<Chunks<Self::Error>, Self::Error> {
+        Ok(Box::new(Some(Ok(self.load(date)?)).into_iter()))
+    }
+
+    /// Whether there is data for a given date, i.e. loading it fails only because of an error.
+    /// Default implementation checks whether the date is within `range`.
+    fn contains(&self, date: &Date<Utc>) -> bool {
+        self.range().map_or(false, |(first, last)| first <= *date && *date <= last)
+    }
+
+    /// Value which changes whenever data for a given date does, used to tell which days have
+    /// changed since they were processed, see `InvertedIndex::update`. Default implementation
+    /// returns None, i.e. it is unknown whether data has changed.
+    fn fingerprint(&self, _date: &Date<Utc>) -> Option<u64> {
+        None
+    }
+
+}
+
+/// Every day within the range is a partition, days missing from the log are loaded as errors
+impl<T: DailyChatLog> ChatLog for T {
+
+    type Partition = Date<Utc>;
+
+    type Error = T::Error;
+
+    fn partitions(&self) -> Vec<Date<Utc>> {
+        let mut partitions = Vec::new();
+        if let Some((first, last)) = self.range() {
+            let mut date = first;
+            while date <= last {
+                partitions.push(date);
+                date = day_after(date);
+            }
+        }
+        partitions
+    }
+
+    fn load_partition(&mut self, partition: &Date<Utc>) -> Result<Messages, T::Error> {
+        self.load(partition)
+    }
+
+    fn load_partition_stream(&mut self, partition: &Date<Utc>) -> Result<Chunks<T::Error>, T::Error> {
+        self.load_stream(partition)
+    }
+
+}
+
+/// Chat log stored in a local directory, one file per day. Files are matched by `pattern`, which
+/// is a chrono format string, e.g. `%Y-%m-%d.txt`. Compressed files have compression extension
+/// after the pattern, e.g. `2019-07-01.txt.gz`. Uncompressed files are memory-mapped, so they
+/// must not be modified in place while loaded data is alive; replacing them is fine.
+///
+/// Lines are parsed one by one with `parser`, and messages of each file are sorted by timestamp
+/// by `load`, as there is no telling how the archive was recorded. `load_stream` can not sort a
+/// file it only sees a part of at a time, so it requires files to be ordered by time and fails
+/// with `InvalidData` error at the first message that is out of order.
+pub struct DirectoryLog {
+    root_path: PathBuf,
+    pattern: String,
+    parser: LineParser,
+    /// Files in directory ordered by date
+    files: Vec<(Date<Utc>, PathBuf)>,
+}
+
+impl DirectoryLog {
+
+    pub const DEFAULT_PATTERN: &'static str = "%Y-%m-%d.txt";
+
+    /// Open directory, parsing lines with `parser`, e.g. `LogFormat::line_parser()`
+    pub fn open(root_path: PathBuf, pattern: &str, parser: LineParser) -> io::Result<DirectoryLog> {
+        let mut log = DirectoryLog { root_path, pattern: pattern.to_string(), parser, files: Vec::new() };
+        log.rescan()?;
+        Ok(log)
+    }
+
+    /// Scan directory for files again, e.g. after new files were added
+    pub fn rescan(&mut self) -> io::Result<()> {
+        self.files.clear();
+        for entry in std::fs::read_dir(&self.root_path)? {
+            let path = entry?.path();
+            let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+            let stem = Compression::from_path(&path).strip_extension(file_name);
+            // files not matching the pattern are not logs, skip them
+            if let Ok(date) = NaiveDate::parse_from_str(stem, &self.pattern) {
+                self.files.push((Date::from_utc(date, Utc), path));
+            }
+        }
+        self.files.sort_unstable();
+        // the same date can be stored with different compression, keep only one of them
+        self.files.dedup_by_key(|f| f.0);
+        Ok(())
+    }
+
+    /// Log files with their dates
+    pub fn files(&self) -> &[(Date<Utc>, PathBuf)] {
+        &self.files
+    }
+
+    fn find(&self, date: &Date<Utc>) -> io::Result<&PathBuf> {
+        self.files
+            .binary_search_by_key(date, |f| f.0)
+            .map(|idx| &self.files[idx].1)
+            .map_err(|_| io::Error::new(
+                io::ErrorKind::NotFound, format!("No log file for {} in {:?}", date.format("%Y-%m-%d"), self.root_path)
+            ))
+    }
+
+}
+
+impl DailyChatLog for DirectoryLog {
+
+    fn range(&self) -> Option<(Date<Utc>, Date<Utc>)> {
+        Some((self.files.first()?.0, self.files.last()?.0))
+    }
+
+    type Error = io::Error;
+
+    fn load(&mut self, date: &Date<Utc>) -> io::Result<Messages> {
+        let path = self.find(date)?;
+        match Compression::from_path(path) {
+            // This is safe as long as the file is not modified while mapped, see `DirectoryLog`
+            Compression::None => unsafe { Messages::from_file_with_metadata(path, self.parser, true) },
+            compression => compression.read_to_string(path)
+                .map(|data| Messages::from_string_with_metadata(data, self.parser, true))
+        }
+    }
+
+    fn load_stream(&mut self, date: &Date<Utc>) -> io::Result<Chunks<io::Error>> {
+        let path = self.find(date)?;
+        let reader = Compression::from_path(path).open(path)?;
+        let parser = self.parser;
+        let mut last = None;
+        Ok(Box::new(
+            MessageStream::new(BufReader::new(reader), move |data| Messages::from_string_with_metadata(data, parser, false))
+                .map(move |chunk| chunk.and_then(|messages| {
+                    for message in messages.vec() {
+                        if last.map_or(false, |last| message.timestamp() < last) {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData, format!("message at {} is out of order", message.timestamp())
+                            ));
+                        }
+                        last = Some(message.timestamp());
+                    }
+                    Ok(messages)
+                }))
+        ))
+    }
+
+    fn contains(&self, date: &Date<Utc>) -> bool {
+        self.find(date).is_ok()
+    }
+
+    /// Computed from size and modification time of the file
+    fn fingerprint(&self, date: &Date<Utc>) -> Option<u64> {
+        let metadata = std::fs::metadata(self.find(date).ok()?).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&modified.as_nanos().to_le_bytes());
+        Some((metadata.len() << 32) | hasher.finalize() as u64)
+    }
+
+}
+
+/// Several daily chat logs viewed as one, e.g. logs of several channels combined. Messages are
+/// tagged with the name of the source they came from, see `Message::channel`.
+pub struct MergedChatLog<L: DailyChatLog> {
+    sources: Vec<(String, L)>,
+}
+
+/// Error occurred when loading data of `MergedChatLog`
+#[derive(Debug)]
+pub enum MergedError<E> {
+    /// None of the sources has data for the date
+    Missing(Date<Utc>),
+    /// Some of the sources failed to load data, with their names
+    Sources(Vec<(String, E)>),
+}
+
+impl<E: Display> Display for MergedError<E> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MergedError::Missing(date) => write!(f, "{} is not in any of the sources", date),
+            MergedError::Sources(errors) => {
+                write!(f, "some of the sources failed to load")?;
+                for (i, (name, err)) in errors.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { ":" } else { ";" }, name, err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for MergedError<E> {}
+
+/// Merges chunk streams of `MergedChatLog` sources into a single time-ordered stream. Chunks
+/// of different sources are merged together only as long as they overlap in time, so sources
+/// which are active at different times are still streamed a chunk at a time.
+struct MergedStream<E> {
+    /// Stream of each source, with its next non-empty chunk if it has already been read
+    sources: Vec<(String, Chunks<E>, Option<Messages>)>,
+    /// Error to yield after chunks read before it
+    error: Option<MergedError<E>>,
+    failed: bool,
+}
+
+impl<E> MergedStream<E> {
+
+    fn new(streams: Vec<(String, Chunks<E>)>) -> MergedStream<E> {
+        MergedStream { sources: streams.into_iter().map(|(name, stream)| (name, stream, None)).collect(), error: None, failed: false }
+    }
+
+    /// Read the next non-empty chunk of every source which doesn't have one
+    fn fill(&mut self) -> Result<(), MergedError<E>> {
+        for (name, stream, next) in self.sources.iter_mut().filter(|s| s.2.is_none()) {
+            *next = stream
+                .find(|chunk| chunk.as_ref().map_or(true, |c| !c.vec().is_empty()))
+                .transpose()
+                .map_err(|err| MergedError::Sources(vec![(name.clone(), err)]))?;
+        }
+        Ok(())
+    }
+
+    /// Take the next chunk starting before `before` (any if None), earliest one first
+    fn take_next(&mut self, before: Option<DateTime<Utc>>) -> Option<(String, Messages)> {
+        let (name, _, next) = self.sources.iter_mut()
+            .filter(|s| s.2.as_ref().map_or(false, |c| before.map_or(true, |b| c.vec()[0].timestamp() < b)))
+            .min_by_key(|s| s.2.as_ref().unwrap().vec()[0].timestamp())?;
+        Some((name.clone(), next.take().unwrap()))
+    }
+
+}
+
+impl<E> Iterator for MergedStream<E> {
+
+    type Item = Result<Messages, MergedError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(err) = self.error.take() {
+            self.failed = true;
+            return Some(Err(err));
+        }
+        let mut group = Vec::new();
+        let mut group_end = None;
+        loop {
+            if let Err(err) = self.fill() {
+                self.error = Some(err);
+                break;
+            }
+            match self.take_next(group_end) {
+                Some((name, chunk)) => {
+                    let chunk_end = chunk.vec().last().unwrap().timestamp();
+                    group_end = Some(group_end.map_or(chunk_end, |end| std::cmp::max(end, chunk_end)));
+                    group.push((name, chunk));
+                },
+                None => break
+            }
+        }
+        if group.is_empty() {
+            self.failed = true;
+            self.error.take().map(Err)
+        } else {
+            Some(Ok(Messages::merge(group)))
+        }
+    }
+
+}
+
+impl<L: DailyChatLog> MergedChatLog<L> {
+
+    /// Merge named sources, e.g. chat logs of channels with channel names
+    pub fn new(sources: Vec<(String, L)>) -> MergedChatLog<L> {
+        MergedChatLog { sources }
+    }
+
+    pub fn sources(&self) -> &[(String, L)] {
+        &self.sources
+    }
+
+    pub fn into_sources(self) -> Vec<(String, L)> {
+        self.sources
+    }
+
+}
+
+impl<L: DailyChatLog> DailyChatLog for MergedChatLog<L> {
+
+    /// Union of ranges of all the sources
+    fn range(&self) -> Option<(Date<Utc>, Date<Utc>)> {
+        let ranges = self.sources.iter().filter_map(|(_, l)| l.range());
+        ranges.fold(None, |acc, (first, last)| match acc {
+            Some((acc_first, acc_last)) => Some((std::cmp::min(acc_first, first), std::cmp::max(acc_last, last))),
+            None => Some((first, last))
+        })
+    }
+
+    type Error = MergedError<L::Error>;
+
+    /// Loads the date from every source which has it. Fails if any of them fails, or if none of
+    /// them has the date.
+    fn load(&mut self, date: &Date<Utc>) -> Result<Messages, Self::Error> {
+        let mut loaded = Vec::with_capacity(self.sources.len());
+        let mut errors = Vec::new();
+        for (name, log) in self.sources.iter_mut().filter(|(_, l)| l.contains(date)) {
+            match log.load(date) {
+                Ok(messages) => loaded.push((name.clone(), messages)),
+                Err(err) => errors.push((name.clone(), err))
+            }
+        }
+        if !errors.is_empty() {
+            Err(MergedError::Sources(errors))
+        } else if loaded.is_empty() {
+            Err(MergedError::Missing(*date))
+        } else {
+            Ok(Messages::merge(loaded))
+        }
+    }
+
+    /// Streams the date from every source which has it, see `MergedStream`
+    fn load_stream(&mut self, date: &Date<Utc>) -> Result<Chunks<Self::Error>, Self::Error> {
+        let mut streams = Vec::with_capacity(self.sources.len());
+        let mut errors = Vec::new();
+        for (name, log) in self.sources.iter_mut().filter(|(_, l)| l.contains(date)) {
+            match log.load_stream(date) {
+                Ok(stream) => streams.push((name.clone(), stream)),
+                Err(err) => errors.push((name.clone(), err))
+            }
+        }
+        if !errors.is_empty() {
+            Err(MergedError::Sources(errors))
+        } else if streams.is_empty() {
+            Err(MergedError::Missing(*date))
+        } else {
+            Ok(Box::new(MergedStream::new(streams)))
+        }
+    }
+
+    fn contains(&self, date: &Date<Utc>) -> bool {
+        self.sources.iter().any(|(_, l)| l.contains(date))
+    }
+
+    /// Combined fingerprints of the sources, None if none of them knows its fingerprint. Hashed
+    /// with 64-bit FNV-1a, which is stable across builds, as fingerprints are stored on disk.
+    fn fingerprint(&self, date: &Date<Utc>) -> Option<u64> {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+        let fingerprints = self.sources.iter().map(|(_, l)| l.fingerprint(date)).collect::<Vec<_>>();
+        if fingerprints.iter().all(|f| f.is_none()) {
+            return None;
+        }
+        let bytes = fingerprints.iter().flat_map(|f| {
+            let (known, value) = f.map_or((0u8, 0u64), |f| (1, f));
+            std::iter::once(known).chain(value.to_le_bytes().to_vec())
+        });
+        Some(bytes.fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME)))
+    }
+
+}
+
+/// Chat log which lets caller decide what sliding should do about partitions failing to load,
+/// e.g. to retry transient network errors or to abort on the first missing day. `handler`
+/// receives partition, error and number of retries made so far, see `ChatLog::on_load_error`.
+pub struct ErrorHandlingLog<L: ChatLog, H> {
+    inner: L,
+    handler: H,
+}
+
+impl<L, H> ErrorHandlingLog<L, H>
+    where
+        L: ChatLog,
+        H: FnMut(&L::Partition, &L::Error, u32) -> LoadErrorAction
+{
+
+    pub fn new(inner: L, handler: H) -> ErrorHandlingLog<L, H> {
+        ErrorHandlingLog { inner, handler }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+}
+
+impl<L, H> ChatLog for ErrorHandlingLog<L, H>
+    where
+        L: ChatLog,
+        H: FnMut(&L::Partition, &L::Error, u32) -> LoadErrorAction
+{
+
+    type Partition = L::Partition;
+    type Error = L::Error;
+
+    fn partitions(&self) -> Vec<Self::Partition> {
+        self.inner.partitions()
+    }
+
+    fn load_partition(&mut self, partition: &Self::Partition) -> Result<Messages, Self::Error> {
+        self.inner.load_partition(partition)
+    }
+
+    fn load_partition_stream(&mut self, partition: &Self::Partition) -> Result<Chunks<Self::Error>, Self::Error> {
+        self.inner.load_partition_stream(partition)
+    }
+
+    fn on_load_error(&mut self, partition: &Self::Partition, error: &Self::Error, attempt: u32) -> LoadErrorAction {
+        (self.handler)(partition, error, attempt)
+    }
+
+}
+
+/// Call window function for messages between positions `from` (inclusive) and `to` (exclusive)
+/// in loaded partitions, see `ChatLog::slide_segments`
+fn emit_segment<F>(partitions: &VecDeque<(Messages, Range<usize>)>, from: (usize, usize), to: (usize, usize), f: &mut F)
+    where
+        F: FnMut(&DateTime<Utc>, &DateTime<Utc>, &mut dyn Iterator<Item=&Message>) -> ()
+{
+    let slice = |k: usize| {
+        let (messages, range) = &partitions[k];
+        let lo = if k == from.0 { from.1 } else { range.start };
+        let hi = if k == to.0 { to.1 } else { range.end };
+        &messages.vec()[lo..hi]
+    };
+    let t0 = slice(from.0).first().unwrap().timestamp();
+    let t1 = (from.0..=to.0).rev().map(slice).find_map(|s| s.last()).unwrap().timestamp();
+    let mut window = (from.0..=to.0).flat_map(|k| slice(k).iter());
+    f(&t0, &t1, &mut window);
+}
+
+/// Load partition with `load`, asking `log` what to do whenever it fails. Returns None for
+/// partitions which should be skipped.
+fn load_or_handle<L, T, Load>(log: &mut L, partition: &L::Partition, load: Load) -> Result<Option<T>, SlideErrorKind>
+    where
+        L: ChatLog + ?Sized,
+        Load: Fn(&mut L, &L::Partition) -> Result<T, L::Error>
+{
+    let mut attempt = 0;
+    loop {
+        let err = match load(log, partition) {
+            Ok(data) => return Ok(Some(data)),
+            Err(err) => err
+        };
+        match log.on_load_error(partition, &err, attempt) {
+            LoadErrorAction::Skip => return Ok(None),
+            LoadErrorAction::Retry => attempt += 1,
+            LoadErrorAction::Abort => return Err(SlideErrorKind::LoadFailed { start: partition.start(), end: partition.end() }),
+        }
+    }
+}
+
+/// Time span covered by partitions, or None if there are none
+fn partitions_range<P: Partition>(partitions: &[P]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    Some((partitions.first()?.start(), partitions.last()?.end()))
+}
+
+/// Checks whether interval [start; end] can be slided through with windows of given size
+/// for a chatlog covering given time span.
+fn check_interval(
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>, start: &DateTime<Utc>, end: &DateTime<Utc>, size: u32
+) -> Result<(), SlideError> {
+    let error = |kind| SlideError { kind, requested: (*start, *end), size, available: range };
+    let (t0, t1) = match range {
+        Some(range) => range,
+        None => {
+            return Err(error(SlideErrorKind::NotEnoughData));
+        }
+    };
+
+    if start > end || *start < t0 || *end > t1 {
+        return Err(error(SlideErrorKind::InvalidTimeInterval));
+    }
+
+    if chrono::Duration::seconds(size as i64) > t1 - t0 {
+        return Err(error(SlideErrorKind::NotEnoughData));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::overrustle::parse_string;
+    use crate::tokenizer::{Whitespace, UnicodeWords, Lowercase};
+    use test::Bencher;
+
+    /// Chat log rotated hourly, with `messages_per_hour` messages evenly spread across each hour
+    /// and sent by three users in turn
+    struct HourlyLog {
+        hours: Vec<Hour>,
+        messages_per_hour: u32,
+        /// Number of times loading an hour fails before it succeeds
+        failures: HashMap<Hour, u32>,
+        /// Hours whose stream fails after the first message
+        broken_streams: HashSet<Hour>,
+    }
+
+    impl HourlyLog {
+        fn new(first: Hour, n_hours: u32, messages_per_hour: u32) -> HourlyLog {
+            let hours = std::iter::successors(Some(first), |h| Some(h.next())).take(n_hours as usize).collect();
+            HourlyLog { hours, messages_per_hour, failures: HashMap::new(), broken_streams: HashSet::new() }
+        }
+
+        fn check_failures(&mut self, partition: &Hour) -> Result<(), ()> {
+            match self.failures.get_mut(partition).filter(|f| **f > 0) {
+                Some(failures) => {
+                    *failures -= 1;
+                    Err(())
+                },
+                None => Ok(())
+            }
+        }
+
+        fn lines(&self, partition: &Hour) -> Vec<String> {
+            let interval = 3600 / self.messages_per_hour as i64;
+            (0..self.messages_per_hour as i64)
+                .map(|i| (partition.start() + chrono::Duration::seconds(i * interval))
+                    .format(&format!("[%Y-%m-%d %H:%M:%S UTC] user{}: hello Kappa {} PogChamp {}\n", i % 3, i % 7, i % 13))
+                    .to_string())
+                .collect()
+        }
+    }
+
+    impl ChatLog for HourlyLog {
+        type Partition = Hour;
+        type Error = ();
+
+        fn partitions(&self) -> Vec<Hour> {
+            self.hours.clone()
+        }
+
+        fn load_partition(&mut self, partition: &Hour) -> Result<Messages, ()> {
+            self.check_failures(partition)?;
+            Ok(parse_string(self.lines(partition).concat()))
+        }
+
+        /// Every message is a separate chunk
+        fn load_partition_stream(&mut self, partition: &Hour) -> Result<Chunks<()>, ()> {
+            self.check_failures(partition)?;
+            let mut chunks = self.lines(partition).into_iter().map(|line| Ok(parse_string(line))).collect::<Vec<_>>();
+            if self.broken_streams.contains(partition) {
+                chunks.truncate(1);
+                chunks.push(Err(()));
+            }
+            Ok(Box::new(chunks.into_iter()))
+        }
+    }
+
+    #[test]
+    fn test_directory_log() {
+        let path = std::env::temp_dir().join(format!("chatan-test-directory-log-{}", std::process::id()));
+        std::fs::create_dir_all(&path).expect("Could not create test directory");
+        // messages are sorted even if the archive has them out of order
+        std::fs::write(path.join("log-20190701.txt"), "[2019-07-01 00:00:43 UTC] user3: late\n[2019-07-01 00:00:42 UTC] user1: first\n")
+            .expect("Could not write test file");
+        Compression::Gzip.write(&path.join("log-20190703.txt.gz"), &mut "[2019-07-03 00:00:42 UTC] user2: second\n".as_bytes())
+            .expect("Could not write test file");
+        std::fs::write(path.join("notes.txt"), "not a log").expect("Could not write test file");
+
+        let mut log = DirectoryLog::open(path.clone(), "log-%Y%m%d.txt", crate::message::LogFormat::OverRustle.line_parser())
+            .expect("Could not open directory");
+        assert_eq!(log.range(), Some((Utc.ymd(2019, 7, 1), Utc.ymd(2019, 7, 3))));
+        assert_eq!(log.load(&Utc.ymd(2019, 7, 3)).expect("Could not load log").vec()[0].user(), "user2");
+        assert!(log.load(&Utc.ymd(2019, 7, 2)).is_err());
+        let users = |messages: &Messages| messages.vec().iter().map(|m| m.user().to_string()).collect::<Vec<_>>();
+        assert_eq!(users(&log.load(&Utc.ymd(2019, 7, 1)).expect("Could not load log")), vec!["user1", "user3"]);
+        // streaming can not sort the file, so it refuses to yield unordered messages
+        let streamed = log.load_stream(&Utc.ymd(2019, 7, 1)).expect("Could not load log").collect::<Vec<_>>();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].as_ref().err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        let streamed = log.load_stream(&Utc.ymd(2019, 7, 3)).expect("Could not load log")
+            .collect::<Result<Vec<_>, _>>().expect("Could not read log");
+        assert_eq!(users(&streamed[0]), vec!["user2"]);
+
+        let mut users = Vec::new();
+        log.slide(log.span().unwrap().0, log.span().unwrap().1, 86400 * 3, 86400 * 3, |_, _, win| {
+            users.extend(win.map(|m| m.user().to_string()));
+        }).expect("Should be able to slide");
+        assert_eq!(users, vec!["user1", "user3", "user2"]);
+        std::fs::remove_dir_all(&path).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn test_merged_chat_log() {
+        let root = std::env::temp_dir().join(format!("chatan-test-merged-log-{}", std::process::id()));
+        for (channel, date, line) in &[
+            ("a", "2019-07-01", "[2019-07-01 00:00:42 UTC] user1: first"),
+            ("a", "2019-07-02", "[2019-07-02 00:00:44 UTC] user1: third"),
+            ("b", "2019-07-02", "[2019-07-02 00:00:43 UTC] user2: second"),
+            ("b", "2019-07-03", "[2019-07-03 00:00:45 UTC] user2: fourth"),
+        ] {
+            std::fs::create_dir_all(root.join(channel)).expect("Could not create test directory");
+            std::fs::write(root.join(channel).join(format!("{}.txt", date)), format!("{}\n", line))
+                .expect("Could not write test file");
+        }
+        let open = |channel: &str| DirectoryLog::open(
+            root.join(channel), DirectoryLog::DEFAULT_PATTERN, crate::message::LogFormat::OverRustle.line_parser()
+        ).expect("Could not open directory");
+
+        let mut log = MergedChatLog::new(vec![("a".to_string(), open("a")), ("b".to_string(), open("b"))]);
+        assert_eq!(log.range(), Some((Utc.ymd(2019, 7, 1), Utc.ymd(2019, 7, 3))));
+        let messages = log.load(&Utc.ymd(2019, 7, 2)).expect("Could not load merged log");
+        let messages = messages.vec().iter().map(|m| (m.channel().unwrap(), m.message())).collect::<Vec<_>>();
+        assert_eq!(messages, vec![("b", "second"), ("a", "third")]);
+        assert_eq!(log.load(&Utc.ymd(2019, 7, 3)).expect("Could not load merged log").vec().len(), 1);
+        // fingerprints of the sources are combined without truncating them
+        let fingerprint = log.fingerprint(&Utc.ymd(2019, 7, 2)).expect("Sources have fingerprints");
+        assert_ne!(Some(fingerprint), log.fingerprint(&Utc.ymd(2019, 7, 3)));
+        assert!(fingerprint > u32::max_value() as u64);
+        let streamed = log.load_stream(&Utc.ymd(2019, 7, 2)).expect("Could not load merged log")
+            .collect::<Result<Vec<_>, _>>().expect("Could not read merged log");
+        assert_eq!(streamed.iter().map(|c| c.vec().len()).collect::<Vec<_>>(), vec![1, 1]);
+
+        // a source which has the date but fails to load it is an error, not a missing day
+        std::fs::remove_file(root.join("b").join("2019-07-03.txt")).expect("Could not remove test file");
+        match log.load(&Utc.ymd(2019, 7, 3)) {
+            Err(MergedError::Sources(errors)) => assert_eq!(errors.iter().map(|e| e.0.as_str()).collect::<Vec<_>>(), vec!["b"]),
+            other => panic!("Unexpected result {:?}", other.map(|m| m.vec().len()))
+        }
+        assert!(log.load_stream(&Utc.ymd(2019, 7, 3)).is_err());
+        let mut log = MergedChatLog::new(vec![("a".to_string(), open("a"))]);
+        assert!(match log.load(&Utc.ymd(2019, 7, 3)) { Err(MergedError::Missing(_)) => true, _ => false });
+        std::fs::remove_dir_all(&root).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn test_merged_stream() {
+        let chunk = |seconds: &[u32]| Ok(parse_string(seconds.iter()
+            .map(|s| format!("[2019-07-01 00:00:{:02} UTC] user: {}\n", s, s))
+            .collect()));
+        let a: Chunks<()> = Box::new(vec![chunk(&[0, 1]), chunk(&[]), chunk(&[5, 6])].into_iter());
+        let b: Chunks<()> = Box::new(vec![chunk(&[2, 3]), chunk(&[4, 7])].into_iter());
+        // chunks are merged only while they overlap
+        let merged = MergedStream::new(vec![("a".to_string(), a), ("b".to_string(), b)])
+            .map(|c| c.unwrap().vec().iter().map(|m| format!("{}{}", m.channel().unwrap(), m.message())).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(merged, vec![vec!["a0", "a1"], vec!["b2", "b3"], vec!["b4", "a5", "a6", "b7"]]);
+
+        let a: Chunks<()> = Box::new(vec![chunk(&[0, 1]), Err(()), chunk(&[5, 6])].into_iter());
+        let b: Chunks<()> = Box::new(vec![chunk(&[2, 3])].into_iter());
+        let merged = MergedStream::new(vec![("a".to_string(), a), ("b".to_string(), b)]).collect::<Vec<_>>();
+        assert_eq!(merged.len(), 2);
+        assert!(match &merged[1] { Err(MergedError::Sources(errors)) => errors[0].0 == "a", _ => false });
+    }
+
+    #[test]
+    fn test_slide_hourly() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(10, 30, 0));
+        let mut log = HourlyLog::new(first, 3, 1);
+        let mut counts = Vec::new();
+        log.slide(first.start(), first.start() + chrono::Duration::hours(3), 3600, 5400, |_, _, win| {
+            counts.push(win.count());
+        }).expect("Should be able to slide");
+        // 10:00-11:30, 11:00-12:30
+        assert_eq!(counts, vec![2, 2]);
+    }
+
+    fn collect_stats(stats: &mut Vec<(u64, u64, u64, Vec<(String, u64)>)>, win: WindowStats) {
+        let mut counts = win.token_counts.iter().map(|(t, c)| (t.to_string(), *c)).collect::<Vec<_>>();
+        counts.sort();
+        stats.push((win.n_messages, win.n_tokens, win.n_tokens_filtered, counts));
+    }
+
+    #[test]
+    fn test_slide_token_counts_incremental() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let mut log = HourlyLog::new(first, 12, 7);
+        let (start, end) = log.span().unwrap();
+        let tokenizers: Vec<Box<dyn Tokenizer>> = vec![Box::new(Whitespace), Box::new(Lowercase(UnicodeWords))];
+        let modes = [CountingMode::Occurrences, CountingMode::Messages, CountingMode::Users];
+        for (tokenizer, &counting) in tokenizers.iter().flat_map(|t| modes.iter().map(move |m| (t, m))) {
+            let options = TokenCountOptions::new(&**tokenizer)
+                .with_counting(counting)
+                .with_message_filter(|m: &Message| m.message().len() % 2 == 0)
+                .with_token_filter(|t: &str| t != "hello");
+            for &(step, size) in &[(600, 3600), (3600, 3600), (1800, 7200), (7200, 1800), (999, 4321)] {
+                let mut expected = Vec::new();
+                log.slide_token_counts(start, end, step, size, |_, _, win| collect_stats(&mut expected, win), &options)
+                    .expect("Should be able to slide");
+                let mut actual = Vec::new();
+                log.slide_token_counts_incremental(start, end, step, size, |_, _, win| collect_stats(&mut actual, win), &options)
+                    .expect("Should be able to slide");
+                assert!(!expected.is_empty());
+                assert_eq!(expected, actual, "step = {}, size = {}, counting = {:?}", step, size, counting);
+            }
+        }
+    }
+
+    #[test]
+    fn test_counting_modes() {
+        let messages = parse_string(concat!(
+            "[2019-07-01 00:00:42 UTC] user1: Kappa Kappa Kappa\n",
+            "[2019-07-01 00:00:43 UTC] user1: Kappa\n",
+            "[2019-07-01 00:00:44 UTC] user2: Kappa LUL\n",
+        ).to_string());
+        let count = |counting| {
+            let stats = window_stats(&mut messages.vec().iter(), &TokenCountOptions::new(&Whitespace).with_counting(counting));
+            assert_eq!((stats.n_tokens, stats.n_tokens_filtered), (6, 6));
+            (stats.token_counts[&Cow::Borrowed("Kappa")], stats.token_counts[&Cow::Borrowed("LUL")])
+        };
+        assert_eq!(count(CountingMode::Occurrences), (5, 1));
+        assert_eq!(count(CountingMode::Messages), (3, 1));
+        assert_eq!(count(CountingMode::Users), (2, 1));
+    }
+
+    #[test]
+    fn test_ngrams_and_phrases() {
+        let messages = parse_string(concat!(
+            "[2019-07-01 00:00:42 UTC] user1: WE ARE READY\n",
+            "[2019-07-01 00:00:43 UTC] user2: we are ready\n",
+            "[2019-07-01 00:00:44 UTC] user1: WE ARE READY\n",
+            "[2019-07-01 00:00:45 UTC] user3: READY\n",
+        ).to_string());
+        let tokenizer = Lowercase(Whitespace);
+
+        let bigrams = window_ngrams(&mut messages.vec().iter(), &|_: &Message| true, &tokenizer, 2);
+        let mut bigrams = bigrams.iter().map(|(t, c)| (t.to_string(), *c)).collect::<Vec<_>>();
+        bigrams.sort();
+        assert_eq!(bigrams, vec![("are ready".to_string(), 3), ("we are".to_string(), 3)]);
+
+        let phrases = window_phrases(&mut messages.vec().iter(), &|_: &Message| true, &tokenizer, 2);
+        let phrases = phrases.iter().map(|(t, c)| (t.to_string(), *c)).collect::<Vec<_>>();
+        assert_eq!(phrases, vec![("we are ready".to_string(), 2)]);
+
+        // unigrams are just tokens
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let mut log = HourlyLog::new(first, 6, 7);
+        let (start, end) = log.span().unwrap();
+        let mut expected = Vec::new();
+        log.slide_token_counts(start, end, 1800, 3600, |_, _, win| collect_stats(&mut expected, win), &TokenCountOptions::new(&Whitespace))
+            .expect("Should be able to slide");
+        let mut actual = Vec::new();
+        log.slide_ngrams(start, end, 1800, 3600, |_, _, counts| {
+            let mut counts = counts.iter().map(|(t, c)| (t.to_string(), *c)).collect::<Vec<_>>();
+            counts.sort();
+            actual.push(counts);
+        }, |_| true, &Whitespace, 1).expect("Should be able to slide");
+        assert_eq!(expected.into_iter().map(|s| s.3).collect::<Vec<_>>(), actual);
+    }
+
+    #[test]
+    fn test_slide_user_stats() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let mut log = HourlyLog::new(first, 3, 4);
+        let (start, end) = log.span().unwrap();
+        let mut windows = Vec::new();
+        log.slide_user_stats(start, end, 1800, 1800, |_, _, win| {
+            let top = win.top_chatters(1)[0];
+            windows.push((win.n_chatters(), win.n_new_chatters, win.n_returning_chatters, top.0.to_string(), top.1));
+        }, |_| true, &Whitespace).expect("Should be able to slide");
+        // messages are sent every 15 minutes by user0, user1, user2, user0 each hour, and ties
+        // of top chatters are broken by name
+        let stats = ChatterStats { n_messages: 1, n_tokens: 5 };
+        assert_eq!(windows[..3].to_vec(), vec![
+            (2, 2, 0, "user0".to_string(), stats),
+            (2, 1, 1, "user0".to_string(), stats),
+            (2, 0, 2, "user0".to_string(), stats),
+        ]);
+
+        let messages = parse_string(concat!(
+            "[2019-07-01 00:00:42 UTC] user1: a b\n",
+            "[2019-07-01 00:00:43 UTC] user2: a\n",
+            "[2019-07-01 00:00:44 UTC] user1: c\n",
+        ).to_string());
+        let stats = window_user_stats(&mut messages.vec().iter(), &|_: &Message| true, &Whitespace, &mut HashSet::new());
+        assert_eq!(stats.chatters["user1"], ChatterStats { n_messages: 2, n_tokens: 3 });
+        assert_eq!(stats.messages_per_chatter().into_iter().collect::<Vec<_>>(), vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn test_slide_partial() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(10, 0, 0));
+        let mut log = HourlyLog::new(first, 3, 1);
+        log.hours.remove(1);
+        let start = first.start() - chrono::Duration::hours(1);
+        let end = first.start() + chrono::Duration::hours(3);
+
+        let err = log.slide(start, end, 3600, 3600, |_, _, _| ()).err().expect("Interval is not covered");
+        assert_eq!(err.kind, SlideErrorKind::InvalidTimeInterval);
+        assert_eq!(err.available, Some((first.start(), first.start() + chrono::Duration::hours(3))));
+
+        let mut windows = Vec::new();
+        log.slide_partial(start, end, 3600, 3600, |_, _, coverage, win| {
+            windows.push((coverage.missing.len(), win.count()))
+        }).expect("Should be able to slide");
+        // 09:00 is before the log and 11:00 is missing, so windows starting then are empty
+        assert_eq!(windows, vec![(1, 0), (0, 1), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let failing = first.next();
+        let make_log = |failures| {
+            let mut log = HourlyLog::new(first, 3, 2);
+            log.failures.insert(failing, failures);
+            log
+        };
+        let (start, end) = make_log(0).span().unwrap();
+        // every window contains the first message of its hour only
+        fn count_windows<L: ChatLog>(log: &mut L, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<usize>, SlideError> {
+            let mut counts = Vec::new();
+            log.slide(start, end, 3600, 1200, |_, _, win| counts.push(win.count()))?;
+            Ok(counts)
+        }
+        let retry_3_times = |_: &Hour, _: &(), attempt| if attempt < 3 { LoadErrorAction::Retry } else { LoadErrorAction::Abort };
+
+        // failed hours are skipped by default
+        assert_eq!(count_windows(&mut make_log(1), start, end), Ok(vec![1, 0, 1]));
+        let mut missing = Vec::new();
+        make_log(1).slide_partial(start, end, 3600, 1200, |_, _, coverage, _| missing.push(coverage.is_complete()))
+            .expect("Should be able to slide");
+        assert_eq!(missing, vec![true, false, true]);
+
+        let mut log = ErrorHandlingLog::new(make_log(2), retry_3_times);
+        assert_eq!(count_windows(&mut log, start, end), Ok(vec![1, 1, 1]));
+
+        let aborted = SlideErrorKind::LoadFailed { start: failing.start(), end: failing.end() };
+        let mut log = ErrorHandlingLog::new(make_log(5), retry_3_times);
+        assert_eq!(count_windows(&mut log, start, end).map_err(|e| e.kind), Err(aborted));
+        let mut log = ErrorHandlingLog::new(make_log(5), retry_3_times);
+        assert_eq!(log.par_slide(start, end, 3600, 1200, |_, _, win| win.count()).map_err(|e| e.kind), Err(aborted));
+        let mut log = ErrorHandlingLog::new(make_log(5), retry_3_times);
+        assert_eq!(log.slide_stream(start, end, 3600, 1200, |_, _, _| ()).map_err(|e| e.kind), Err(aborted));
+        let mut log = ErrorHandlingLog::new(make_log(5), retry_3_times);
+        assert_eq!(log.slide_sessions(start, end, 3600, |_, _, _| ()).map_err(|e| e.kind), Err(aborted));
+        let mut log = ErrorHandlingLog::new(make_log(5), retry_3_times);
+        let result = log.slide_token_counts_incremental(start, end, 3600, 1200, |_, _, _| (), &TokenCountOptions::new(&Whitespace));
+        assert_eq!(result.map_err(|e| e.kind), Err(aborted));
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let make_log = || {
+            let mut log = HourlyLog::new(first, 3, 2);
+            log.broken_streams.insert(first.next());
+            log
+        };
+        let (start, end) = make_log().span().unwrap();
+        let mut counts = Vec::new();
+        make_log().slide_stream(start, end, 1800, 1800, |_, _, win| counts.push(win.count()))
+            .expect("Should be able to slide");
+        // rest of the broken hour is skipped by default
+        assert_eq!(counts, vec![1, 1, 1, 0, 1, 1]);
+
+        let failing = first.next();
+        let aborted = SlideErrorKind::LoadFailed { start: failing.start(), end: failing.end() };
+        for action in vec![LoadErrorAction::Retry, LoadErrorAction::Abort] {
+            let mut log = ErrorHandlingLog::new(make_log(), move |_: &Hour, _: &(), _| action);
+            assert_eq!(log.slide_stream(start, end, 1800, 1800, |_, _, _| ()).map_err(|e| e.kind), Err(aborted));
+        }
+    }
+
+    #[test]
+    fn test_slide_segments() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        // two sessions of three hours, with 10 minutes between messages
+        let mut log = HourlyLog::new(first, 8, 6);
+        log.hours.drain(3..5);
+        let (start, end) = log.span().unwrap();
+
+        let mut sessions = Vec::new();
+        log.slide_sessions(start, end, 1800, |t0, t1, win| sessions.push((*t0, *t1, win.count())))
+            .expect("Should be able to slide");
+        assert_eq!(sessions, vec![
+            (first.start(), first.start() + chrono::Duration::minutes(170), 18),
+            (first.start() + chrono::Duration::hours(5), first.start() + chrono::Duration::minutes(470), 18),
+        ]);
+
+        let mut counts = Vec::new();
+        log.slide_message_count(start, end, 5, |_, _, win| counts.push(win.count()))
+            .expect("Should be able to slide");
+        assert_eq!(counts, vec![5, 5, 5, 5, 5, 5, 5, 1]);
+    }
+
+    #[test]
+    fn test_par_slide() {
+        let first = Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0));
+        let mut log = HourlyLog::new(first, 100, 3);
+        let (start, end) = log.span().unwrap();
+        let mut expected = Vec::new();
+        log.slide(start, end, 1800, 7200, |t0, _, win| expected.push((*t0, win.count())))
+            .expect("Should be able to slide");
+        let actual = log.par_slide(start, end, 1800, 7200, |t0, _, win| (*t0, win.count()))
+            .expect("Should be able to slide");
+        assert_eq!(expected, actual);
+    }
+
+    #[bench]
+    fn bench_slide_token_counts(b: &mut Bencher) {
+        let mut log = HourlyLog::new(Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0)), 48, 500);
+        let (start, end) = log.span().unwrap();
+        b.iter(|| log.slide_token_counts(start, end, 3600, 86400, |_, _, _| (), &TokenCountOptions::new(&Whitespace)).ok())
+    }
+
+    #[bench]
+    fn bench_slide_token_counts_incremental(b: &mut Bencher) {
+        let mut log = HourlyLog::new(Hour::containing(&Utc.ymd(2019, 7, 1).and_hms(0, 0, 0)), 48, 500);
+        let (start, end) = log.span().unwrap();
+        b.iter(|| log.slide_token_counts_incremental(start, end, 3600, 86400, |_, _, _| (), &TokenCountOptions::new(&Whitespace)).ok())
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{self, Read, Write, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// Compression level used when writing zstd files; 0 means zstd default
+const ZSTD_LEVEL: i32 = 0;
+
+/// Compression of log files stored on disk. Compressed files have additional extension
+/// appended to their names, e.g. `2019-07-01.txt.gz`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+
+    pub const ALL: [Compression; 3] = [Compression::None, Compression::Gzip, Compression::Zstd];
+
+    /// Extension which is appended to the name of file compressed this way
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Detect compression by file extension
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Remove extension of this compression from file name, if present
+    pub fn strip_extension<'a>(&self, file_name: &'a str) -> &'a str {
+        if !self.extension().is_empty() && file_name.ends_with(self.extension()) {
+            &file_name[..file_name.len() - self.extension().len()]
+        } else {
+            file_name
+        }
+    }
+
+    /// Wrap reader so that it decompresses data
+    pub fn reader<R: Read + 'static>(&self, reader: R) -> io::Result<Box<dyn Read>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+
+    /// Open file and decompress its contents on the fly
+    pub fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        self.reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut data = String::new();
+        self.open(path)?.read_to_string(&mut data)?;
+        Ok(data)
+    }
+
+    /// Write all data from reader into file at path, compressing it. Returns number of
+    /// uncompressed bytes written.
+    pub fn write<R: Read + ?Sized>(&self, path: &Path, data: &mut R) -> io::Result<u64> {
+        let file = File::create(path)?;
+        match self {
+            Compression::None => {
+                let mut file = file;
+                io::copy(data, &mut file)
+            },
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                let written = io::copy(data, &mut encoder)?;
+                encoder.finish()?.flush()?;
+                Ok(written)
+            },
+            Compression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(file, ZSTD_LEVEL)?;
+                let written = io::copy(data, &mut encoder)?;
+                encoder.finish()?.flush()?;
+                Ok(written)
+            },
+        }
+    }
+
+    /// Same as `write`, but data is first written into a temporary file next to `path`, which
+    /// then replaces it. This way `path` is never left partially written.
+    pub fn write_atomic<R: Read + ?Sized>(&self, path: &Path, data: &mut R) -> io::Result<u64> {
+        self.write_atomic_if(path, data, |_| true).map(|written| written.unwrap_or(0))
+    }
+
+    /// Same as `write_atomic`, but `path` is only replaced if `keep` returns true once all the
+    /// data is written, e.g. if it differs from what `path` already has. Otherwise returns
+    /// None. Temporary file never outlives the call.
+    pub fn write_atomic_if<R, F>(&self, path: &Path, data: &mut R, keep: F) -> io::Result<Option<u64>>
+        where
+            R: Read + ?Sized,
+            F: FnOnce(&mut R) -> bool
+    {
+        let tmp_path = path.with_extension("part");
+        let result = self.write(&tmp_path, data).and_then(|written| {
+            if keep(data) {
+                std::fs::rename(&tmp_path, path).map(|_| Some(written))
+            } else {
+                Ok(None)
+            }
+        });
+        if let Ok(Some(_)) = result {
+            return result;
+        }
+        // nothing else to do if it can't be removed either
+        let _ = std::fs::remove_file(&tmp_path);
+        result
+    }
+
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(s.to_string())
+        }
+    }
+}
+
+impl ToString for Compression {
+    fn to_string(&self) -> String {
+        match self {
+            Compression::None => "None".to_string(),
+            Compression::Gzip => "Gzip".to_string(),
+            Compression::Zstd => "Zstd".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let data = "[2019-07-01 00:00:42 UTC] someuser: FeelsGoodMan\n";
+        for compression in Compression::ALL.iter() {
+            let path = std::env::temp_dir()
+                .join(format!("chatan-test-roundtrip.txt{}", compression.extension()));
+            compression.write_atomic(&path, &mut data.as_bytes()).expect("Could not write file");
+            assert!(!path.with_extension("part").exists());
+            assert_eq!(Compression::from_path(&path), *compression);
+            assert_eq!(compression.read_to_string(&path).expect("Could not read file"), data);
+            std::fs::remove_file(&path).expect("Could not remove file");
+        }
+    }
+
+    #[test]
+    fn test_write_atomic_if() {
+        let path = std::env::temp_dir().join(format!("chatan-test-write-atomic-if-{}.txt", std::process::id()));
+        std::fs::write(&path, "old\n").expect("Could not write file");
+        // rejected data leaves the file and no temporary file behind
+        let written = Compression::None.write_atomic_if(&path, &mut "new\n".as_bytes(), |_| false)
+            .expect("Could not write file");
+        assert_eq!(written, None);
+        assert_eq!(std::fs::read_to_string(&path).expect("Could not read file"), "old\n");
+        assert!(!path.with_extension("part").exists());
+
+        let written = Compression::None.write_atomic_if(&path, &mut "new\n".as_bytes(), |_| true)
+            .expect("Could not write file");
+        assert_eq!(written, Some(4));
+        assert_eq!(std::fs::read_to_string(&path).expect("Could not read file"), "new\n");
+        std::fs::remove_file(&path).expect("Could not remove file");
+    }
+}
@@ -0,0 +1,365 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use structopt::StructOpt;
+
+use crate::chatlog::{ChatLog, Chunks, LoadErrorAction};
+use crate::message::{Message, Messages};
+
+/// Bots commonly found in Twitch chats
+pub const KNOWN_BOTS: [&str; 14] = [
+    "nightbot", "streamelements", "streamlabs", "moobot", "fossabot", "wizebot", "deepbot",
+    "phantombot", "botisimo", "coebot", "vivbot", "scorpbot", "stay_hydrated_bot", "commanderroot",
+];
+
+/// Single stage of `FilterPipeline`
+pub trait FilterStage: FilterStageClone + Send + Sync {
+
+    /// Returns `None` if message should be dropped, otherwise its text, possibly rewritten.
+    /// `text` is the text of the message as returned by the previous stages.
+    fn apply<'a>(&mut self, message: &'a Message, text: Cow<'a, str>) -> Option<Cow<'a, str>>;
+
+    /// Forget everything seen so far. Called when messages stop being consecutive, i.e. when
+    /// a partition or chunk starts before the previous one ended, so that stages never compare
+    /// messages which are out of order.
+    fn reset(&mut self) {}
+
+}
+
+/// Cloning of boxed stages, implemented for every `FilterStage` which is `Clone`
+pub trait FilterStageClone {
+    fn clone_box(&self) -> Box<dyn FilterStage>;
+}
+
+impl<T: FilterStage + Clone + 'static> FilterStageClone for T {
+    fn clone_box(&self) -> Box<dyn FilterStage> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn FilterStage> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Keeps messages of listed users only, or drops them. Users are matched case-insensitively.
+#[derive(Debug, Clone)]
+pub struct UserFilter {
+    users: HashSet<String>,
+    allow: bool,
+}
+
+impl UserFilter {
+
+    pub fn allow<I: IntoIterator<Item=S>, S: AsRef<str>>(users: I) -> UserFilter {
+        UserFilter { users: users.into_iter().map(|u| u.as_ref().to_lowercase()).collect(), allow: true }
+    }
+
+    pub fn deny<I: IntoIterator<Item=S>, S: AsRef<str>>(users: I) -> UserFilter {
+        UserFilter { users: users.into_iter().map(|u| u.as_ref().to_lowercase()).collect(), allow: false }
+    }
+
+    /// Drops messages of `KNOWN_BOTS`
+    pub fn known_bots() -> UserFilter {
+        UserFilter::deny(KNOWN_BOTS.iter())
+    }
+
+}
+
+impl FilterStage for UserFilter {
+    fn apply<'a>(&mut self, message: &'a Message, text: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        if self.users.contains(&message.user().to_lowercase()) == self.allow {
+            Some(text)
+        } else {
+            None
+        }
+    }
+}
+
+/// Collapses identical messages (e.g. copypastas or spam) sent within `span` from each other
+/// into the first of them, regardless of who sent them. Spans crossing partition boundaries
+/// are collapsed as well, as texts are only forgotten once they are older than `span`.
+#[derive(Debug, Clone)]
+pub struct CollapseDuplicates {
+    span: Duration,
+    last_seen: HashMap<Arc<str>, DateTime<Utc>>,
+    /// Texts in order they were last seen, to forget the ones not seen for longer than `span`.
+    /// Texts are shared with `last_seen`, so each of them is only copied once.
+    queue: VecDeque<(DateTime<Utc>, Arc<str>)>,
+}
+
+impl CollapseDuplicates {
+
+    pub fn new(span: Duration) -> CollapseDuplicates {
+        CollapseDuplicates { span, last_seen: HashMap::new(), queue: VecDeque::new() }
+    }
+
+}
+
+impl FilterStage for CollapseDuplicates {
+
+    fn apply<'a>(&mut self, message: &'a Message, text: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        let timestamp = message.timestamp();
+        while self.queue.front().map_or(false, |(t, _)| *t < timestamp - self.span) {
+            let (t, old) = self.queue.pop_front().unwrap();
+            // text could have been seen again since then
+            if self.last_seen.get(&old) == Some(&t) {
+                self.last_seen.remove(&old);
+            }
+        }
+
+        // every duplicate extends the span, so a long burst of spam is collapsed as a whole
+        let (key, duplicate) = match self.last_seen.get_key_value(text.as_ref()) {
+            Some((key, _)) => (key.clone(), true),
+            None => (Arc::from(text.as_ref()), false)
+        };
+        self.last_seen.insert(key.clone(), timestamp);
+        self.queue.push_back((timestamp, key));
+        if duplicate { None } else { Some(text) }
+    }
+
+    fn reset(&mut self) {
+        self.last_seen.clear();
+        self.queue.clear();
+    }
+
+}
+
+/// Normalises messages so that each whitespace-separated token occurs at most `max` times,
+/// e.g. `Kappa Kappa Kappa LUL` becomes `Kappa LUL` with `max` of 1. Messages which don't
+/// exceed the limit are left intact.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxTokenRepeats {
+    max: usize,
+}
+
+impl MaxTokenRepeats {
+
+    pub fn new(max: usize) -> MaxTokenRepeats {
+        MaxTokenRepeats { max }
+    }
+
+}
+
+impl FilterStage for MaxTokenRepeats {
+    fn apply<'a>(&mut self, _: &'a Message, text: Cow<'a, str>) -> Option<Cow<'a, str>> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let tokens = text.split_whitespace()
+            .filter(|tok| {
+                let count = counts.entry(tok).or_insert(0);
+                *count += 1;
+                *count <= self.max
+            })
+            .collect::<Vec<_>>();
+        if counts.values().all(|c| *c <= self.max) {
+            return Some(text);
+        }
+        let normalised = tokens.join(" ");
+        Some(Cow::Owned(normalised))
+    }
+}
+
+/// Sequence of filter stages applied to messages, in order they were added. Stages keep their
+/// state from one call of `apply` to the next, as long as messages are passed in time order.
+#[derive(Default, Clone)]
+pub struct FilterPipeline {
+    stages: Vec<Box<dyn FilterStage>>,
+    /// Timestamp of the last message passed to `apply`
+    last: Option<DateTime<Utc>>,
+}
+
+impl FilterPipeline {
+
+    pub fn new() -> FilterPipeline {
+        FilterPipeline { stages: Vec::new(), last: None }
+    }
+
+    /// Add a stage to the end of the pipeline
+    pub fn with<S: FilterStage + 'static>(mut self, stage: S) -> FilterPipeline {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Filter messages of a partition or a chunk of it. Stages are reset if `messages` start
+    /// before the previous ones ended.
+    pub fn apply(&mut self, messages: Messages) -> Messages {
+        if self.stages.is_empty() || messages.vec().is_empty() {
+            return messages;
+        }
+        if self.last.map_or(false, |last| messages.vec()[0].timestamp() < last) {
+            self.stages.iter_mut().for_each(|s| s.reset());
+        }
+        self.last = messages.vec().last().map(|m| m.timestamp());
+        let stages = &mut self.stages;
+        messages.filter_map(|m| stages
+            .iter_mut()
+            .try_fold(Cow::Borrowed(m.message()), |text, stage| stage.apply(m, text)))
+    }
+
+}
+
+/// Command line options for building `FilterPipeline`, to be flattened into options of a job
+#[derive(Debug, Clone, Default, StructOpt)]
+pub struct FilterOpt {
+    /// Only keep messages of this user, can be given several times
+    #[structopt(name = "allow-user", long, number_of_values = 1)]
+    pub allow_users: Vec<String>,
+    /// Drop messages of this user, can be given several times
+    #[structopt(name = "deny-user", long, number_of_values = 1)]
+    pub deny_users: Vec<String>,
+    /// Drop messages of well-known bots, e.g. Nightbot
+    #[structopt(name = "skip-bots", long)]
+    pub skip_bots: bool,
+    /// Keep each token at most this many times per message
+    #[structopt(name = "max-token-repeats", long)]
+    pub max_token_repeats: Option<usize>,
+    /// Keep identical messages sent within this many seconds from each other only once
+    #[structopt(name = "collapse-duplicates", long)]
+    pub collapse_duplicates: Option<u32>,
+}
+
+impl FilterOpt {
+
+    /// Pipeline with stages for the given options, in order users, bots, token repeats, duplicates
+    pub fn pipeline(&self) -> FilterPipeline {
+        let mut pipeline = FilterPipeline::new();
+        if !self.allow_users.is_empty() {
+            pipeline = pipeline.with(UserFilter::allow(&self.allow_users));
+        }
+        if !self.deny_users.is_empty() {
+            pipeline = pipeline.with(UserFilter::deny(&self.deny_users));
+        }
+        if self.skip_bots {
+            pipeline = pipeline.with(UserFilter::known_bots());
+        }
+        if let Some(max) = self.max_token_repeats {
+            pipeline = pipeline.with(MaxTokenRepeats::new(max));
+        }
+        if let Some(span) = self.collapse_duplicates {
+            pipeline = pipeline.with(CollapseDuplicates::new(Duration::seconds(span as i64)));
+        }
+        pipeline
+    }
+
+}
+
+/// Chat log whose partitions are passed through `FilterPipeline` as they are loaded, so that
+/// every window of every `ChatLog::slide*` method only sees filtered messages. The pipeline is
+/// shared by all the partitions and streamed chunks, so its state carries over from one to the
+/// next.
+pub struct FilteredLog<L: ChatLog> {
+    inner: L,
+    pipeline: Arc<Mutex<FilterPipeline>>,
+}
+
+impl<L: ChatLog> FilteredLog<L> {
+
+    pub fn new(inner: L, pipeline: FilterPipeline) -> FilteredLog<L> {
+        FilteredLog { inner, pipeline: Arc::new(Mutex::new(pipeline)) }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+}
+
+impl<L: ChatLog> ChatLog for FilteredLog<L> {
+
+    type Partition = L::Partition;
+    type Error = L::Error;
+
+    fn partitions(&self) -> Vec<Self::Partition> {
+        self.inner.partitions()
+    }
+
+    fn load_partition(&mut self, partition: &Self::Partition) -> Result<Messages, Self::Error> {
+        let messages = self.inner.load_partition(partition)?;
+        Ok(self.pipeline.lock().unwrap().apply(messages))
+    }
+
+    fn load_partition_stream(&mut self, partition: &Self::Partition) -> Result<Chunks<Self::Error>, Self::Error> {
+        let pipeline = self.pipeline.clone();
+        let chunks = self.inner.load_partition_stream(partition)?;
+        Ok(Box::new(chunks.map(move |chunk| chunk.map(|messages| pipeline.lock().unwrap().apply(messages)))))
+    }
+
+    fn on_load_error(&mut self, partition: &Self::Partition, error: &Self::Error, attempt: u32) -> LoadErrorAction {
+        self.inner.on_load_error(partition, error, attempt)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::overrustle::parse_string;
+
+    #[test]
+    fn test_filter_pipeline() {
+        let messages = parse_string(concat!(
+            "[2019-07-01 00:00:00 UTC] Nightbot: Follow the stream!\n",
+            "[2019-07-01 00:00:01 UTC] user1: Kappa Kappa Kappa LUL Kappa\n",
+            "[2019-07-01 00:00:02 UTC] user2: Kappa LUL\n",
+            "[2019-07-01 00:00:40 UTC] user3: Kappa LUL\n",
+            "[2019-07-01 00:01:30 UTC] user4: Kappa LUL\n",
+            "[2019-07-01 00:01:31 UTC] spammer: hi\n",
+        ).to_string());
+
+        let mut pipeline = FilterPipeline::new()
+            .with(UserFilter::known_bots())
+            .with(UserFilter::deny(vec!["Spammer"]))
+            .with(MaxTokenRepeats::new(1))
+            .with(CollapseDuplicates::new(Duration::seconds(45)));
+        let filtered = pipeline.apply(messages);
+        let filtered = filtered.vec().iter().map(|m| (m.user(), m.message())).collect::<Vec<_>>();
+        // user2 and user3 repeat user1 within the span, user4 comes too late
+        assert_eq!(filtered, vec![("user1", "Kappa LUL"), ("user4", "Kappa LUL")]);
+
+        let messages = parse_string("[2019-07-01 00:00:00 UTC] User1: hi\n[2019-07-01 00:00:01 UTC] user2: hi\n".to_string());
+        let filtered = FilterPipeline::new().with(UserFilter::allow(vec!["user1"])).apply(messages);
+        assert_eq!(filtered.vec().iter().map(|m| m.user()).collect::<Vec<_>>(), vec!["User1"]);
+    }
+
+    #[test]
+    fn test_filter_stream() {
+        let chunks = vec![
+            parse_string("[2019-07-01 00:00:00 UTC] user1: Kappa\n[2019-07-01 00:00:01 UTC] Nightbot: hi\n".to_string()),
+            parse_string("[2019-07-01 00:00:02 UTC] user2: Kappa\n[2019-07-01 00:00:03 UTC] user3: LUL\n".to_string()),
+        ];
+        let opt = FilterOpt { skip_bots: true, collapse_duplicates: Some(10), ..FilterOpt::default() };
+        let mut pipeline = opt.pipeline();
+        // duplicates are collapsed across chunks of the same partition
+        let filtered = chunks.into_iter()
+            .map(|chunk| pipeline.apply(chunk).vec().iter().map(|m| m.user().to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(filtered, vec![vec!["user1"], vec!["user3"]]);
+
+        // ... and across midnight, as long as they are within the span
+        let before = parse_string("[2019-07-01 23:59:55 UTC] user1: PogChamp\n".to_string());
+        let after = parse_string("[2019-07-02 00:00:04 UTC] user2: PogChamp\n[2019-07-02 00:00:20 UTC] user3: PogChamp\n".to_string());
+        assert_eq!(pipeline.apply(before).vec().len(), 1);
+        let filtered = pipeline.apply(after);
+        assert_eq!(filtered.vec().iter().map(|m| m.user()).collect::<Vec<_>>(), vec!["user3"]);
+
+        // going back in time resets the state
+        let again = parse_string("[2019-07-01 00:00:00 UTC] user4: PogChamp\n".to_string());
+        assert_eq!(pipeline.apply(again).vec().len(), 1);
+
+        let opt = FilterOpt::from_iter_safe(&["filter", "--deny-user", "a", "--deny-user", "b", "--skip-bots"])
+            .expect("Should be able to parse arguments");
+        assert_eq!(opt.deny_users, vec!["a", "b"]);
+        assert!(opt.skip_bots);
+    }
+
+}
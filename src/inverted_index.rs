@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::{Date, NaiveDate, Utc};
+use memmap::Mmap;
+use serde::{Serialize, Deserialize};
+
+use crate::chatlog::DailyChatLog;
+use crate::message::Messages;
+use crate::tokenizer::{Tokenizer, Lowercase, Whitespace};
+use crate::util::day_after;
+
+const MANIFEST_FILE: &str = "index.json";
+const MANIFEST_VERSION: u32 = 1;
+
+const SEGMENT_MAGIC: &[u8; 4] = b"CHIX";
+const SEGMENT_VERSION: u32 = 1;
+/// Magic, version, number of terms, size of keys blob
+const SEGMENT_HEADER_SIZE: usize = 16;
+/// Key offset, key size, postings offset, number of postings
+const SEGMENT_TERM_SIZE: usize = 16;
+/// Message, position
+const SEGMENT_POSTING_SIZE: usize = 8;
+
+const KEY_TOKEN: u8 = 0;
+const KEY_USER: u8 = 1;
+
+/// Message of a chat log found in the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hit {
+    pub date: Date<Utc>,
+    /// Position of the message among messages loaded for `date`, i.e. in
+    /// `DailyChatLog::load(date)?.vec()`
+    pub message: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct DayInfo {
+    n_messages: u32,
+    /// See `DailyChatLog::fingerprint`
+    fingerprint: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    days: BTreeMap<NaiveDate, DayInfo>,
+}
+
+/// On-disk inverted index of a daily chat log, mapping tokens and users to messages they occur
+/// in. Index consists of a segment per day, `<root_path>/YYYY-MM-DD.idx`, and a manifest of
+/// indexed days, `<root_path>/index.json`, so it can be updated day by day, e.g. after
+/// `OverRustleLogs::sync`, and queried without loading the logs themselves.
+///
+/// Index should always be opened with the tokenizer it was built with.
+pub struct InvertedIndex<T: Tokenizer = Lowercase<Whitespace>> {
+    root_path: PathBuf,
+    tokenizer: T,
+    days: BTreeMap<NaiveDate, DayInfo>,
+}
+
+impl InvertedIndex {
+
+    /// Open index in `root_path`, tokens are case-insensitive and separated by whitespace
+    pub fn open(root_path: PathBuf) -> io::Result<InvertedIndex> {
+        InvertedIndex::open_with_tokenizer(root_path, Lowercase(Whitespace))
+    }
+
+}
+
+impl<T: Tokenizer> InvertedIndex<T> {
+
+    /// Open index in `root_path`, or create an empty one if there is none
+    pub fn open_with_tokenizer(root_path: PathBuf, tokenizer: T) -> io::Result<InvertedIndex<T>> {
+        std::fs::create_dir_all(&root_path)?;
+        let path = root_path.join(MANIFEST_FILE);
+        let days = if path.is_file() {
+            let manifest: Manifest = serde_json::from_reader(BufReader::new(File::open(&path)?))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if manifest.version == MANIFEST_VERSION { manifest.days } else { BTreeMap::new() }
+        } else {
+            BTreeMap::new()
+        };
+        Ok(InvertedIndex { root_path, tokenizer, days })
+    }
+
+    /// Indexed days, in order
+    pub fn days(&self) -> Vec<Date<Utc>> {
+        self.days.keys().map(|d| Date::from_utc(*d, Utc)).collect()
+    }
+
+    /// Number of messages indexed for a given day
+    pub fn n_messages(&self, date: &Date<Utc>) -> Option<u32> {
+        self.days.get(&date.naive_utc()).map(|d| d.n_messages)
+    }
+
+    /// Index messages of a single day, replacing whatever was indexed for it before
+    pub fn add_day(&mut self, date: &Date<Utc>, messages: &Messages, fingerprint: Option<u64>) -> io::Result<()> {
+        self.write_segment(date, messages, fingerprint)?;
+        self.save_manifest()
+    }
+
+    /// Index days of `logs` which are not indexed yet or have changed since they were indexed.
+    /// When logs can't tell whether a day has changed, the last indexed day is indexed again,
+    /// as it might have been incomplete. Days which fail to load are skipped and will be tried
+    /// again on the next update. Returns days which were indexed.
+    pub fn update<L: DailyChatLog>(&mut self, logs: &mut L) -> io::Result<Vec<Date<Utc>>> {
+        let (first, last) = match logs.range() {
+            Some(range) => range,
+            None => return Ok(Vec::new())
+        };
+        let last_indexed = self.days.keys().next_back().cloned();
+
+        let mut updated = Vec::new();
+        let mut date = first;
+        while date <= last {
+            let fingerprint = logs.fingerprint(&date);
+            let stale = match self.days.get(&date.naive_utc()) {
+                None => true,
+                Some(info) => match fingerprint {
+                    Some(_) => info.fingerprint != fingerprint,
+                    None => Some(date.naive_utc()) == last_indexed,
+                }
+            };
+            if stale {
+                if let Ok(messages) = logs.load(&date) {
+                    self.write_segment(&date, &messages, fingerprint)?;
+                    updated.push(date);
+                }
+            }
+            date = day_after(date);
+        }
+
+        if !updated.is_empty() {
+            self.save_manifest()?;
+        }
+        Ok(updated)
+    }
+
+    /// Messages containing `token`. Token is normalised by the tokenizer of this index, so
+    /// e.g. `Kappa` finds `kappa` with the default one.
+    pub fn lookup_token(&self, token: &str) -> io::Result<Vec<Hit>> {
+        self.lookup_phrase(token)
+    }
+
+    /// Messages containing tokens of `phrase` one after another
+    pub fn lookup_phrase(&self, phrase: &str) -> io::Result<Vec<Hit>> {
+        let keys = self.tokenizer.tokens(phrase)
+            .iter()
+            .map(|t| make_key(KEY_TOKEN, t))
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut hits = Vec::new();
+        for date in self.days.keys() {
+            let segment = Segment::open(&self.segment_path(date))?;
+            let postings = keys.iter().map(|k| segment.postings(k)).collect::<Vec<_>>();
+            let mut last = None;
+            for &(message, position) in postings[0].iter() {
+                if last == Some(message) {
+                    continue;
+                }
+                let matches = postings[1..].iter().enumerate().all(|(i, p)| {
+                    p.binary_search(&(message, position + 1 + i as u32)).is_ok()
+                });
+                if matches {
+                    hits.push(Hit { date: Date::from_utc(*date, Utc), message });
+                    last = Some(message);
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Messages sent by `user`, case-insensitively
+    pub fn lookup_user(&self, user: &str) -> io::Result<Vec<Hit>> {
+        let key = make_key(KEY_USER, &user.to_lowercase());
+        let mut hits = Vec::new();
+        for date in self.days.keys() {
+            let segment = Segment::open(&self.segment_path(date))?;
+            hits.extend(segment.postings(&key).iter().map(|(message, _)| Hit { date: Date::from_utc(*date, Utc), message: *message }));
+        }
+        Ok(hits)
+    }
+
+    fn segment_path(&self, date: &NaiveDate) -> PathBuf {
+        self.root_path.join(date.format("%Y-%m-%d.idx").to_string())
+    }
+
+    fn write_segment(&mut self, date: &Date<Utc>, messages: &Messages, fingerprint: Option<u64>) -> io::Result<()> {
+        let mut terms: BTreeMap<Vec<u8>, Vec<(u32, u32)>> = BTreeMap::new();
+        for (i, message) in messages.vec().iter().enumerate() {
+            let i = i as u32;
+            terms.entry(make_key(KEY_USER, &message.user().to_lowercase())).or_default().push((i, 0));
+            let mut position = 0;
+            self.tokenizer.tokenize(message.message(), &mut |tok| {
+                terms.entry(make_key(KEY_TOKEN, &tok)).or_default().push((i, position));
+                position += 1;
+            });
+        }
+
+        let path = self.segment_path(&date.naive_utc());
+        let tmp_path = path.with_extension("part");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write_segment(&mut writer, &terms)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, &path)?;
+
+        self.days.insert(date.naive_utc(), DayInfo { n_messages: messages.vec().len() as u32, fingerprint });
+        Ok(())
+    }
+
+    fn save_manifest(&self) -> io::Result<()> {
+        let path = self.root_path.join(MANIFEST_FILE);
+        let manifest = Manifest { version: MANIFEST_VERSION, days: self.days.clone() };
+        let tmp_path = path.with_extension("part");
+        let file = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(file, &manifest)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        std::fs::rename(&tmp_path, &path)
+    }
+
+}
+
+fn make_key(kind: u8, term: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(term.len() + 1);
+    key.push(kind);
+    key.extend_from_slice(term.as_bytes());
+    key
+}
+
+/// Segment layout, all numbers are little-endian u32:
+/// * header: magic, version, number of terms, size of keys blob
+/// * terms sorted by key: key offset and size in keys blob, postings offset (in postings)
+///   and number of postings
+/// * keys blob
+/// * postings: (message, position) pairs, sorted
+fn write_segment<W: Write>(writer: &mut W, terms: &BTreeMap<Vec<u8>, Vec<(u32, u32)>>) -> io::Result<()> {
+    let keys_size: usize = terms.keys().map(|k| k.len()).sum();
+    writer.write_all(SEGMENT_MAGIC)?;
+    for value in &[SEGMENT_VERSION, terms.len() as u32, keys_size as u32] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    let mut key_offset = 0;
+    let mut postings_offset = 0;
+    for (key, postings) in terms.iter() {
+        for value in &[key_offset, key.len() as u32, postings_offset, postings.len() as u32] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        key_offset += key.len() as u32;
+        postings_offset += postings.len() as u32;
+    }
+    for key in terms.keys() {
+        writer.write_all(key)?;
+    }
+    for postings in terms.values() {
+        for (message, position) in postings {
+            writer.write_all(&message.to_le_bytes())?;
+            writer.write_all(&position.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Memory-mapped segment of a single day, see `write_segment` for layout
+struct Segment {
+    mmap: Mmap,
+    n_terms: usize,
+    keys_start: usize,
+    postings_start: usize,
+}
+
+impl Segment {
+
+    fn open(path: &PathBuf) -> io::Result<Segment> {
+        let file = File::open(path)?;
+        // This is safe as long as segment is not modified while mapped. Segments are replaced
+        // by renaming, which doesn't affect files which are already mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid index segment {:?}", path));
+        if mmap.len() < SEGMENT_HEADER_SIZE || &mmap[..4] != SEGMENT_MAGIC || read_u32(&mmap, 4) != SEGMENT_VERSION {
+            return Err(invalid());
+        }
+        let n_terms = read_u32(&mmap, 8) as usize;
+        let keys_size = read_u32(&mmap, 12) as usize;
+        let keys_start = n_terms.checked_mul(SEGMENT_TERM_SIZE)
+            .and_then(|size| size.checked_add(SEGMENT_HEADER_SIZE))
+            .ok_or_else(invalid)?;
+        let postings_start = keys_start.checked_add(keys_size).ok_or_else(invalid)?;
+        if mmap.len() < postings_start {
+            return Err(invalid());
+        }
+
+        // every term should point inside the segment, so lookups never have to check it
+        let n_postings = (mmap.len() - postings_start) / SEGMENT_POSTING_SIZE;
+        let segment = Segment { mmap, n_terms, keys_start, postings_start };
+        let in_bounds = |offset: usize, size: usize, total: usize| offset.checked_add(size).map_or(false, |end| end <= total);
+        for idx in 0..n_terms {
+            let (key_offset, key_size, postings_offset, n) = segment.term_offsets(idx);
+            if !in_bounds(key_offset, key_size, keys_size) || !in_bounds(postings_offset, n, n_postings) {
+                return Err(invalid());
+            }
+        }
+        Ok(segment)
+    }
+
+    /// Key offset in keys blob, key size, postings offset in postings and number of postings
+    fn term_offsets(&self, idx: usize) -> (usize, usize, usize, usize) {
+        let offset = SEGMENT_HEADER_SIZE + idx * SEGMENT_TERM_SIZE;
+        (
+            read_u32(&self.mmap, offset) as usize,
+            read_u32(&self.mmap, offset + 4) as usize,
+            read_u32(&self.mmap, offset + 8) as usize,
+            read_u32(&self.mmap, offset + 12) as usize,
+        )
+    }
+
+    /// Key of a term, its postings offset and number of postings. Offsets are checked in `open`
+    fn term(&self, idx: usize) -> (&[u8], usize, usize) {
+        let (key_offset, key_size, postings_offset, n_postings) = self.term_offsets(idx);
+        let key_offset = self.keys_start + key_offset;
+        (&self.mmap[key_offset..key_offset + key_size], postings_offset, n_postings)
+    }
+
+    /// Postings of a given key, empty if there is no such key
+    fn postings(&self, key: &[u8]) -> Vec<(u32, u32)> {
+        let (mut lo, mut hi) = (0, self.n_terms);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (term, offset, n) = self.term(mid);
+            match term.cmp(key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let start = self.postings_start + offset * SEGMENT_POSTING_SIZE;
+                    return (0..n)
+                        .map(|i| start + i * SEGMENT_POSTING_SIZE)
+                        .map(|p| (read_u32(&self.mmap, p), read_u32(&self.mmap, p + 4)))
+                        .collect();
+                }
+            }
+        }
+        Vec::new()
+    }
+
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatlog::DirectoryLog;
+    use crate::message::LogFormat;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_inverted_index() {
+        let root = std::env::temp_dir().join(format!("chatan-test-inverted-index-{}", std::process::id()));
+        let logs_path = root.join("logs");
+        std::fs::create_dir_all(&logs_path).expect("Could not create test directory");
+        std::fs::write(logs_path.join("2019-07-01.txt"), concat!(
+            "[2019-07-01 00:00:42 UTC] user1: WE ARE READY\n",
+            "[2019-07-01 00:00:43 UTC] User2: are we ready Kappa\n",
+        )).expect("Could not write test file");
+        std::fs::write(logs_path.join("2019-07-02.txt"), "[2019-07-02 00:00:42 UTC] user2: we are not ready\n")
+            .expect("Could not write test file");
+        let mut logs = DirectoryLog::open(logs_path.clone(), "%Y-%m-%d.txt", LogFormat::OverRustle.line_parser())
+            .expect("Could not open directory");
+
+        let mut index = InvertedIndex::open(root.join("index")).expect("Could not open index");
+        assert_eq!(index.update(&mut logs).expect("Could not update index").len(), 2);
+        // nothing has changed
+        assert!(index.update(&mut logs).expect("Could not update index").is_empty());
+
+        let day = |d| Utc.ymd(2019, 7, d);
+        let hit = |d, message| Hit { date: day(d), message };
+        assert_eq!(index.lookup_token("kappa").unwrap(), vec![hit(1, 1)]);
+        assert_eq!(index.lookup_token("READY").unwrap(), vec![hit(1, 0), hit(1, 1), hit(2, 0)]);
+        assert_eq!(index.lookup_phrase("we are").unwrap(), vec![hit(1, 0), hit(2, 0)]);
+        assert_eq!(index.lookup_phrase("are ready").unwrap(), vec![hit(1, 0)]);
+        assert_eq!(index.lookup_user("user2").unwrap(), vec![hit(1, 1), hit(2, 0)]);
+        assert!(index.lookup_token("LUL").unwrap().is_empty());
+
+        // only the changed day is indexed again, and the index is persisted
+        std::fs::write(logs_path.join("2019-07-02.txt"), "[2019-07-02 00:00:42 UTC] user3: Kappa\n")
+            .expect("Could not write test file");
+        let mut logs = DirectoryLog::open(logs_path, "%Y-%m-%d.txt", LogFormat::OverRustle.line_parser())
+            .expect("Could not open directory");
+        assert_eq!(index.update(&mut logs).expect("Could not update index"), vec![day(2)]);
+        let index = InvertedIndex::open(root.join("index")).expect("Could not open index");
+        assert_eq!(index.days(), vec![day(1), day(2)]);
+        assert_eq!(index.lookup_token("Kappa").unwrap(), vec![hit(1, 1), hit(2, 0)]);
+        assert!(index.lookup_user("user2").unwrap().iter().all(|h| h.date == day(1)));
+
+        // corrupt segments are reported instead of crashing lookups
+        let segment_path = root.join("index").join("2019-07-02.idx");
+        let mut data = std::fs::read(&segment_path).expect("Could not read segment");
+        data[SEGMENT_HEADER_SIZE + 8..SEGMENT_HEADER_SIZE + 12].copy_from_slice(&1000u32.to_le_bytes());
+        std::fs::write(&segment_path, &data).expect("Could not write segment");
+        let err = index.lookup_token("Kappa").err().expect("Segment should be invalid");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::write(&segment_path, &data[..SEGMENT_HEADER_SIZE + SEGMENT_TERM_SIZE]).expect("Could not write segment");
+        assert_eq!(index.lookup_user("user3").err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+
+        std::fs::remove_dir_all(&root).expect("Could not remove test directory");
+    }
+
+}
@@ -8,6 +8,10 @@ extern crate test;
 pub mod emote_index;
 pub mod message;
 pub mod chatlog;
+pub mod tokenizer;
+pub mod filter;
+pub mod inverted_index;
 pub mod util;
+pub mod compression;
 
 pub mod overrustle;
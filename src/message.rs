@@ -1,16 +1,147 @@
 use chrono::{DateTime, Utc};
+use std::io::{self, BufRead};
+use std::fs::File;
+use std::path::Path;
+use memmap::Mmap;
+use std::borrow::Cow;
+
+/// Default size of a chunk of data `MessageStream` reads at once, in bytes
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// IRCv3 metadata of a single message. Tag values borrow from the data they were parsed from,
+/// unless they had to be unescaped.
+#[derive(Debug, Default, Clone)]
+pub struct MessageMetadata<'a> {
+    /// Raw `badges` tag value, e.g. `subscriber/6,bits/100`
+    pub badges: Cow<'a, str>,
+    /// Number of months user is subscribed for (`badge-info` tag)
+    pub subscriber_months: Option<u32>,
+    /// Amount of bits cheered with this message
+    pub bits: Option<u32>,
+    /// User name as it is displayed in chat, e.g. `SomeUser`
+    pub display_name: Option<Cow<'a, str>>,
+    /// User name color, e.g. `#FF4500`
+    pub color: Option<Cow<'a, str>>,
+    /// Unique id of the message
+    pub id: Option<Cow<'a, str>>,
+    /// Id of the message this one is replying to
+    pub reply_parent: Option<Cow<'a, str>>,
+}
+
+impl<'a> MessageMetadata<'a> {
+
+    /// Iterate over (badge name, badge version) pairs
+    pub fn badges(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.badges
+            .split(',')
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                let mut parts = b.splitn(2, '/');
+                (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+            })
+    }
+
+    pub fn has_badge(&self, name: &str) -> bool {
+        self.badges().any(|(badge, _)| badge == name)
+    }
+
+    pub fn is_subscriber(&self) -> bool {
+        self.subscriber_months.is_some() || self.has_badge("subscriber") || self.has_badge("founder")
+    }
+
+    pub fn is_moderator(&self) -> bool {
+        self.has_badge("moderator") || self.has_badge("broadcaster")
+    }
+
+}
+
+/// String of `RawMetadata`, pointing either into the data or to a copy which had to be unescaped
+#[derive(Debug)]
+enum RawStr {
+    Data(*const str),
+    Owned(Box<str>),
+}
+
+impl RawStr {
+
+    fn new(s: &Cow<str>) -> RawStr {
+        match s {
+            Cow::Borrowed(s) => RawStr::Data(*s as *const str),
+            Cow::Owned(s) => RawStr::Owned(s.clone().into_boxed_str()),
+        }
+    }
+
+    /// This is safe to call only when `self` is owned by the same `Messages` as the
+    /// data it points to
+    unsafe fn get(&self) -> &str {
+        match self {
+            RawStr::Data(s) => &**s,
+            RawStr::Owned(s) => s,
+        }
+    }
+
+}
+
+/// Same as `MessageMetadata`, but with pointers instead of references. This is what
+/// `Messages` actually store in their metadata side table.
+#[derive(Debug)]
+struct RawMetadata {
+    badges: RawStr,
+    subscriber_months: Option<u32>,
+    bits: Option<u32>,
+    display_name: Option<RawStr>,
+    color: Option<RawStr>,
+    id: Option<RawStr>,
+    reply_parent: Option<RawStr>,
+}
+
+impl RawMetadata {
+    fn new(meta: &MessageMetadata) -> RawMetadata {
+        RawMetadata {
+            badges: RawStr::new(&meta.badges),
+            subscriber_months: meta.subscriber_months,
+            bits: meta.bits,
+            display_name: meta.display_name.as_ref().map(RawStr::new),
+            color: meta.color.as_ref().map(RawStr::new),
+            id: meta.id.as_ref().map(RawStr::new),
+            reply_parent: meta.reply_parent.as_ref().map(RawStr::new),
+        }
+    }
+
+    /// This is safe to call only when `self` is owned by the same `Messages` as the
+    /// data it points to
+    unsafe fn get(&self) -> MessageMetadata {
+        MessageMetadata {
+            badges: Cow::Borrowed(self.badges.get()),
+            subscriber_months: self.subscriber_months,
+            bits: self.bits,
+            display_name: self.display_name.as_ref().map(|s| Cow::Borrowed(s.get())),
+            color: self.color.as_ref().map(|s| Cow::Borrowed(s.get())),
+            id: self.id.as_ref().map(|s| Cow::Borrowed(s.get())),
+            reply_parent: self.reply_parent.as_ref().map(|s| Cow::Borrowed(s.get())),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Message {
     timestamp: DateTime<Utc>,
     user: *const str,
     message: *const str,
+    /// Points into the metadata side table of the owning `Messages`, or null
+    metadata: *const RawMetadata,
+    /// Points into the channel table of the owning `Messages`, or null for messages which
+    /// don't come from `Messages::merge`
+    channel: *const String,
 }
 
 impl Message {
     /// This method should not be public to prevent constructor misuse
     fn new(timestamp: DateTime<Utc>, user: &str, message: &str) -> Message {
-        Message { timestamp, user: user as *const str, message: message as *const str }
+        Message {
+            timestamp, user: user as *const str, message: message as *const str,
+            metadata: std::ptr::null(), channel: std::ptr::null()
+        }
     }
 
     #[inline(always)]
@@ -29,8 +160,38 @@ impl Message {
     pub fn timestamp(&self) -> DateTime<Utc> {
         self.timestamp
     }
+
+    /// IRCv3 metadata of this message, if parser has provided it
+    #[inline(always)]
+    pub fn metadata(&self) -> Option<MessageMetadata> {
+        // This is safe because `Message` can only be constructed inside of `Messages` struct,
+        // and metadata side table is never modified after construction
+        unsafe { self.metadata.as_ref().map(|m| m.get()) }
+    }
+
+    /// Name of the source this message was merged from, see `Messages::merge`
+    #[inline(always)]
+    pub fn channel(&self) -> Option<&str> {
+        // This is safe because `Message` can only be constructed inside of `Messages` struct,
+        // and channel table is never modified after construction
+        unsafe { self.channel.as_ref().map(|c| c.as_str()) }
+    }
+
+    /// Whether this message was sent by subscriber. Always false for messages without metadata.
+    pub fn is_subscriber(&self) -> bool {
+        self.metadata().map_or(false, |m| m.is_subscriber())
+    }
+
+    /// Whether this message was sent by moderator. Always false for messages without metadata.
+    pub fn is_moderator(&self) -> bool {
+        self.metadata().map_or(false, |m| m.is_moderator())
+    }
 }
 
+// `Message` only points into data owned by its `Messages`, which is never modified after
+// construction, so messages can be read from several threads at once
+unsafe impl Sync for Message {}
+
 impl std::fmt::Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         write!(
@@ -43,20 +204,110 @@ impl std::fmt::Display for Message {
     }
 }
 
+/// Storage for the data `Message`s point to
+enum Data {
+    Owned(String),
+    /// Memory-mapped file. Its contents are checked to be valid UTF-8 upon mapping
+    Mapped(Mmap),
+    /// Other `Messages`, which own the data for merged messages. They are only kept alive
+    #[allow(dead_code)]
+    Merged(Vec<Messages>),
+    /// `Messages` filtered ones were derived from, along with texts which were replaced,
+    /// see `Messages::filter_map`. They are only kept alive
+    #[allow(dead_code)]
+    Filtered(Box<Messages>, Vec<String>),
+}
+
+impl Data {
+
+    /// Caller must ensure that the file is not modified while mapped, see `Messages::from_file`
+    unsafe fn map(path: &Path) -> io::Result<Data> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            // empty files cannot be mapped
+            return Ok(Data::Owned(String::new()));
+        }
+        let mmap = Mmap::map(&file)?;
+        std::str::from_utf8(&mmap[..]).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Data::Mapped(mmap))
+    }
+
+    #[inline(always)]
+    fn as_str(&self) -> &str {
+        match self {
+            Data::Owned(s) => s.as_str(),
+            // This is safe because contents are validated in `Data::map`
+            Data::Mapped(mmap) => unsafe { std::str::from_utf8_unchecked(&mmap[..]) },
+            // merged and filtered messages are never parsed again
+            Data::Merged(_) | Data::Filtered(_, _) => "",
+        }
+    }
+
+}
+
 pub struct Messages {
     /// This data is a base for all the messages in this struct, i.e. any `Message`
-    /// in `self.messages` will have pointers to this
-    data: String,
+    /// in `self.messages` will have pointers to this. Moving `Messages` around doesn't
+    /// move the data itself, neither for heap-allocated strings nor for mapped files.
+    data: Data,
     messages: Vec<Message>,
+    /// Optional metadata for messages. `Message`s which have metadata point into this
+    metadata: Vec<RawMetadata>,
+    /// Names of merged sources. Merged `Message`s point into this
+    channels: Vec<String>,
 }
 
+// Moving `Messages` doesn't move the data messages point to, see `Messages::data`
+unsafe impl Send for Messages {}
+unsafe impl Sync for Messages {}
+
 impl Messages {
 
     pub fn from_string<Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str)>>(
         data: String, parser: Parser, sort_messages: bool
     ) -> Self {
-        let mut res = Messages { data, messages: Vec::new() };
-        res.messages = res.data.split_terminator('\n').filter_map(
+        Messages::from_data(Data::Owned(data), parser, sort_messages)
+    }
+
+    /// Same as `from_string`, but parser can also provide metadata for each message.
+    pub fn from_string_with_metadata<Parser>(data: String, parser: Parser, sort_messages: bool) -> Self
+        where
+            Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str, Option<MessageMetadata>)>
+    {
+        Messages::from_data_with_metadata(Data::Owned(data), parser, sort_messages)
+    }
+
+    /// Same as `from_string`, but file is memory-mapped instead of being read into memory, so
+    /// messages point directly into the mapped file.
+    ///
+    /// # Safety
+    ///
+    /// File must not be modified (e.g. written to or truncated, by this or any other process)
+    /// while returned `Messages` are alive, otherwise they may see invalid UTF-8 or memory
+    /// which is no longer mapped. Replacing the file by renaming another one over it is fine.
+    pub unsafe fn from_file<Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str)>>(
+        path: &Path, parser: Parser, sort_messages: bool
+    ) -> io::Result<Self> {
+        Ok(Messages::from_data(Data::map(path)?, parser, sort_messages))
+    }
+
+    /// Same as `from_file`, but parser can also provide metadata for each message.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `from_file`.
+    pub unsafe fn from_file_with_metadata<Parser>(path: &Path, parser: Parser, sort_messages: bool) -> io::Result<Self>
+        where
+            Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str, Option<MessageMetadata>)>
+    {
+        Ok(Messages::from_data_with_metadata(Data::map(path)?, parser, sort_messages))
+    }
+
+    fn from_data<Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str)>>(
+        data: Data, parser: Parser, sort_messages: bool
+    ) -> Self {
+        let mut res = Messages { data, messages: Vec::new(), metadata: Vec::new(), channels: Vec::new() };
+        res.messages = res.data.as_str().split_terminator('\n').filter_map(
             |line| {
                 let (ts, us, ms) = parser(line)?;
                 Some(Message::new(ts, us, ms))
@@ -68,10 +319,91 @@ impl Messages {
         res
     }
 
+    fn from_data_with_metadata<Parser>(data: Data, parser: Parser, sort_messages: bool) -> Self
+        where
+            Parser: Fn(&str) -> Option<(DateTime<Utc>, &str, &str, Option<MessageMetadata>)>
+    {
+        let mut res = Messages { data, messages: Vec::new(), metadata: Vec::new(), channels: Vec::new() };
+        let parsed = res.data.as_str().split_terminator('\n').filter_map(|line| parser(line)).collect::<Vec<_>>();
+
+        // side table should be fully built before taking pointers to its elements
+        res.metadata = parsed.iter()
+            .filter_map(|(_, _, _, meta)| meta.as_ref().map(RawMetadata::new))
+            .collect();
+
+        let mut metadata = res.metadata.iter();
+        res.messages = parsed.into_iter().map(
+            |(ts, us, ms, meta)| {
+                let mut message = Message::new(ts, us, ms);
+                if meta.is_some() {
+                    message.metadata = metadata.next().unwrap() as *const RawMetadata;
+                }
+                message
+            }
+        ).collect();
+
+        if sort_messages {
+            res.messages.sort_unstable_by_key(|m| m.timestamp);
+        }
+        res
+    }
+
     pub fn empty() -> Self {
         Messages {
-            data: String::new(),
-            messages: Vec::new()
+            data: Data::Owned(String::new()),
+            messages: Vec::new(),
+            metadata: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Merge messages of several sources into a single time-ordered sequence. Each message is
+    /// tagged with the name of its source (e.g. channel), available via `Message::channel`.
+    /// Messages with equal timestamps keep the order of sources.
+    pub fn merge(sources: Vec<(String, Messages)>) -> Self {
+        let (channels, parts): (Vec<String>, Vec<Messages>) = sources.into_iter().unzip();
+        let mut res = Messages { data: Data::Owned(String::new()), messages: Vec::new(), metadata: Vec::new(), channels };
+        res.messages = res.channels.iter()
+            .zip(parts.iter())
+            .flat_map(|(channel, part)| part.messages.iter().map(move |m| Message {
+                timestamp: m.timestamp, user: m.user, message: m.message, metadata: m.metadata,
+                channel: channel as *const String
+            }))
+            .collect();
+        res.messages.sort_by_key(|m| m.timestamp);
+        // moving parts doesn't move the data their messages point to
+        res.data = Data::Merged(parts);
+        res
+    }
+
+    /// Keep only messages for which `f` returns some text, which replaces the text of the
+    /// message. Text borrowed from the message itself is not copied.
+    pub fn filter_map<F>(self, f: F) -> Self
+        where
+            F: for<'a> FnMut(&'a Message) -> Option<Cow<'a, str>>
+    {
+        let mut f = f;
+        let mut texts = Vec::new();
+        let mut messages = Vec::with_capacity(self.messages.len());
+        for m in self.messages.iter() {
+            let text = match f(m) {
+                Some(Cow::Borrowed(text)) => text as *const str,
+                Some(Cow::Owned(text)) => {
+                    // moving `String` into vector doesn't move its contents
+                    texts.push(text);
+                    texts.last().unwrap().as_str() as *const str
+                },
+                None => continue
+            };
+            messages.push(Message {
+                timestamp: m.timestamp, user: m.user, message: text, metadata: m.metadata, channel: m.channel
+            });
+        }
+        Messages {
+            data: Data::Filtered(Box::new(self), texts),
+            messages,
+            metadata: Vec::new(),
+            channels: Vec::new()
         }
     }
 
@@ -81,36 +413,113 @@ impl Messages {
 
     /// Retrieves a slice of messages falling into specified time interval.
     pub fn temporal_slice(&self, t0: &DateTime<Utc>, t1: &DateTime<Utc>) -> &[Message] {
-        if self.messages.is_empty() {
-            &self.messages
-        } else {
-            let first = self.messages.first().unwrap();
-            let last = self.messages.last().unwrap();
-
-            let start_idx = {
-                if *t0 <= first.timestamp() {
-                    0
-                } else {
-                    match self.messages.binary_search_by_key(t0, |m| m.timestamp) {
-                        Ok(x) => x, Err(x) => x
-                    }
-                }
-            };
+        &self.messages[self.temporal_range(t0, t1)]
+    }
+
+    /// Same as `temporal_slice`, but returns indices of messages instead. Interval is half-open,
+    /// i.e. messages sent exactly at `t1` belong to the next interval.
+    pub fn temporal_range(&self, t0: &DateTime<Utc>, t1: &DateTime<Utc>) -> std::ops::Range<usize> {
+        // index of the first message sent at `t` or later, messages might share timestamps
+        let lower_bound = |t: &DateTime<Utc>| self.messages
+            .binary_search_by(|m| if m.timestamp < *t { std::cmp::Ordering::Less } else { std::cmp::Ordering::Greater })
+            .unwrap_or_else(|idx| idx);
+        let start_idx = lower_bound(t0);
+        start_idx..lower_bound(t1).max(start_idx)
+    }
+
+}
+
+/// Reads messages line-by-line from `BufRead`, yielding them in chunks of approximately
+/// `chunk_size` bytes. Each chunk owns its own data, so only chunks which are still in use
+/// have to be kept in memory.
+///
+/// `parse` is a function turning chunk of data into `Messages`, e.g. `overrustle::parse_string`.
+/// Stream stops after the first IO error.
+pub struct MessageStream<R: BufRead, P: Fn(String) -> Messages> {
+    reader: R,
+    parse: P,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl<R: BufRead, P: Fn(String) -> Messages> MessageStream<R, P> {
+
+    pub fn new(reader: R, parse: P) -> Self {
+        MessageStream::with_chunk_size(reader, parse, DEFAULT_CHUNK_SIZE)
+    }
 
-            let end_idx = {
-                if *t1 >= last.timestamp() {
-                    self.messages.len()
-                } else {
-                    match self.messages.binary_search_by_key(t1, |m| m.timestamp) {
-                        Ok(x) => x, Err(x) => x
-                    }
+    pub fn with_chunk_size(reader: R, parse: P, chunk_size: usize) -> Self {
+        MessageStream { reader, parse, chunk_size, done: false }
+    }
+
+}
+
+impl<R: BufRead, P: Fn(String) -> Messages> Iterator for MessageStream<R, P> {
+    type Item = io::Result<Messages>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut chunk = String::new();
+        while chunk.len() < self.chunk_size {
+            match self.reader.read_line(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                },
+                Ok(_) => {},
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
                 }
-            };
+            }
+        }
 
-            &self.messages[start_idx..end_idx]
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok((self.parse)(chunk)))
         }
     }
+}
+
+/// Function parsing a single log line into timestamp, user, message and optional metadata, or
+/// returning None for lines which are not messages. It is given lines without line terminator.
+pub type LineParser = for<'a> fn(&'a str) -> Option<(DateTime<Utc>, &'a str, &'a str, Option<MessageMetadata<'a>>)>;
 
+/// Format of log lines, for logs which don't come with their own parser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[2019-07-01 00:00:42 UTC] user: message`
+    OverRustle,
+    /// Raw IRC lines with IRCv3 tags, as received from Twitch
+    TwitchIrc,
+}
+
+impl LogFormat {
+
+    /// Function parsing lines of this format, e.g. for `Messages::from_file_with_metadata`
+    pub fn line_parser(&self) -> LineParser {
+        match self {
+            LogFormat::OverRustle => overrustle::parse_line_with_metadata,
+            LogFormat::TwitchIrc => twitch_irc::parse_line_with_metadata,
+        }
+    }
+
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "overrustle" => Ok(LogFormat::OverRustle),
+            "twitch-irc" | "irc" => Ok(LogFormat::TwitchIrc),
+            _ => Err(s.to_string())
+        }
+    }
 }
 
 pub mod overrustle {
@@ -164,15 +573,97 @@ pub mod overrustle {
         }
     }
 
+    /// Same as `parse_line`, but with signature of `LineParser`. OverRustle logs have no metadata
+    pub(crate) fn parse_line_with_metadata(line: &str)
+        -> Option<(DateTime<Utc>, &str, &str, Option<MessageMetadata>)> {
+        parse_line(line).map(|(ts, user, message)| (ts, user, message, None))
+    }
+
     pub fn parse_string(s: String) -> Messages {
         // messages on overrustle are already sorted by timestamp, so
         // no need to ensure sorting (artifacts should be negligible)
         Messages::from_string(s, parse_line, false)
     }
 
+    /// Memory-map and parse a log file, see `Messages::from_file`.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `Messages::from_file`.
+    pub unsafe fn parse_file(path: &Path) -> io::Result<Messages> {
+        Messages::from_file(path, parse_line, false)
+    }
+
+    /// User being banned or timed out, as recorded in monthly `bans.txt` files
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct BanEvent {
+        pub timestamp: DateTime<Utc>,
+        pub user: String,
+        /// Duration of a timeout, None for permanent bans
+        pub duration: Option<chrono::Duration>,
+    }
+
+    /// User subscribing to the channel, as recorded in monthly `subscribers.txt` files
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct SubscriptionEvent {
+        pub timestamp: DateTime<Utc>,
+        pub user: String,
+        /// Number of months user is subscribed for, including this one
+        pub months: u32,
+    }
+
+    /// Parses ban line, e.g. `Ban: someuser timed out for 600 seconds` or `Ban: someuser banned`.
+    /// Banned user is always the first word of the message.
+    pub fn parse_ban(message: &Message) -> Option<BanEvent> {
+        let text = message.message();
+        let user = text.split_whitespace().next()?.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+        if user.is_empty() {
+            return None;
+        }
+        let duration = match text.find(" for ") {
+            Some(idx) => Some(parse_duration(&text[idx + " for ".len()..])?),
+            None if text.contains("ban") => None,
+            None => return None
+        };
+        Some(BanEvent { timestamp: message.timestamp(), user: user.to_string(), duration })
+    }
+
+    /// Parses durations like `600 seconds`, `10m` or `1 hour`
+    fn parse_duration(s: &str) -> Option<chrono::Duration> {
+        let s = s.trim_start();
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or_else(|| s.len());
+        let amount = s[..digits].parse::<i64>().ok()?;
+        let unit = s[digits..].trim_start().split(|c: char| !c.is_alphabetic()).next().unwrap_or("");
+        match unit {
+            "" | "s" | "sec" | "second" | "seconds" => Some(chrono::Duration::seconds(amount)),
+            "m" | "min" | "minute" | "minutes" => Some(chrono::Duration::minutes(amount)),
+            "h" | "hour" | "hours" => Some(chrono::Duration::hours(amount)),
+            "d" | "day" | "days" => Some(chrono::Duration::days(amount)),
+            _ => None
+        }
+    }
+
+    /// Parses subscription notification, e.g. `someuser just subscribed!` or
+    /// `someuser subscribed for 5 months in a row!`. Subscribing user is always the first word
+    /// of the message.
+    pub fn parse_subscription(message: &Message) -> Option<SubscriptionEvent> {
+        let text = message.message();
+        if !text.contains("subscribed") {
+            return None;
+        }
+        let user = text.split_whitespace().next()?;
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let months = words.windows(2)
+            .find(|w| w[1].starts_with("month"))
+            .and_then(|w| w[0].parse::<u32>().ok())
+            .unwrap_or(1);
+        Some(SubscriptionEvent { timestamp: message.timestamp(), user: user.to_string(), months })
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
+        use chrono::TimeZone;
         use test::Bencher;
 
         #[test]
@@ -193,6 +684,77 @@ pub mod overrustle {
             }
         }
 
+        #[test]
+        fn test_message_stream() {
+            let data = "[2019-07-01 00:00:42 UTC] user1: first\n\
+                        [2019-07-01 00:00:43 UTC] user2: second\n\
+                        [2019-07-01 00:00:44 UTC] user3: third\n";
+            let chunks = MessageStream::with_chunk_size(std::io::Cursor::new(data), parse_string, 60)
+                .collect::<std::io::Result<Vec<_>>>()
+                .expect("Stream should not fail");
+            let users = chunks.iter()
+                .flat_map(|c| c.vec().iter().map(|m| m.user()))
+                .collect::<Vec<_>>();
+            assert_eq!(chunks.len(), 2);
+            assert_eq!(users, vec!["user1", "user2", "user3"]);
+        }
+
+        #[test]
+        fn test_merge() {
+            let first = parse_string("[2019-07-01 00:00:42 UTC] user1: first\n[2019-07-01 00:00:44 UTC] user1: third\n".to_string());
+            let second = parse_string("[2019-07-01 00:00:43 UTC] user2: second\n".to_string());
+            let merged = Messages::merge(vec![("a".to_string(), first), ("b".to_string(), second)]);
+            let messages = merged.vec().iter().map(|m| (m.channel().unwrap(), m.message())).collect::<Vec<_>>();
+            assert_eq!(messages, vec![("a", "first"), ("b", "second"), ("a", "third")]);
+        }
+
+        #[test]
+        fn test_temporal_range() {
+            let messages = parse_string("[2019-07-01 00:00:42 UTC] user1: a\n[2019-07-01 00:00:43 UTC] user2: b\n\
+                                         [2019-07-01 00:00:43 UTC] user3: c\n[2019-07-01 00:00:44 UTC] user1: d\n".to_string());
+            let t = |s: u32| Utc.ymd(2019, 7, 1).and_hms(0, 0, s);
+            // messages sent at the end of interval belong to the next one
+            assert_eq!(messages.temporal_range(&t(42), &t(43)), 0..1);
+            assert_eq!(messages.temporal_range(&t(43), &t(44)), 1..3);
+            assert_eq!(messages.temporal_range(&t(42), &t(45)), 0..4);
+            assert_eq!(messages.temporal_range(&t(45), &t(50)), 4..4);
+            assert_eq!(messages.temporal_range(&t(44), &t(43)), 3..3);
+        }
+
+        #[test]
+        fn test_parse_file() {
+            let path = std::env::temp_dir().join("chatan-test-parse-file.txt");
+            std::fs::write(&path, "[2019-07-01 00:00:42 UTC] user1: first\n[2019-07-01 00:00:43 UTC] user2: second\n")
+                .expect("Could not write test file");
+            // the file is only removed after messages are dropped
+            let messages = unsafe { parse_file(&path) }.expect("File should be mapped");
+            assert_eq!(messages.vec().len(), 2);
+            assert_eq!(messages.vec()[1].user(), "user2");
+            assert_eq!(messages.vec()[1].message(), "second");
+            drop(messages);
+            std::fs::remove_file(&path).expect("Could not remove test file");
+        }
+
+        #[test]
+        fn test_parse_events() {
+            let bans = parse_string("[2019-07-01 00:00:42 UTC] Ban: user1 timed out for 600 seconds\n\
+                                     [2019-07-01 00:00:43 UTC] Ban: user2 banned\n\
+                                     [2019-07-01 00:00:44 UTC] Ban: user3 timed out for 10m\n".to_string());
+            let bans = bans.vec().iter().filter_map(parse_ban).collect::<Vec<_>>();
+            assert_eq!(bans.iter().map(|b| b.user.as_str()).collect::<Vec<_>>(), vec!["user1", "user2", "user3"]);
+            assert_eq!(bans[0].duration, Some(chrono::Duration::seconds(600)));
+            assert_eq!(bans[1].duration, None);
+            assert_eq!(bans[2].duration, Some(chrono::Duration::minutes(10)));
+
+            let subs = parse_string("[2019-07-01 00:00:42 UTC] twitchnotify: user1 just subscribed!\n\
+                                     [2019-07-01 00:00:43 UTC] twitchnotify: user2 subscribed for 5 months in a row!\n\
+                                     [2019-07-01 00:00:44 UTC] twitchnotify: Thank you for subscribing!\n".to_string());
+            let subs = subs.vec().iter().filter_map(parse_subscription).collect::<Vec<_>>();
+            assert_eq!(subs.len(), 2);
+            assert_eq!((subs[0].user.as_str(), subs[0].months), ("user1", 1));
+            assert_eq!((subs[1].user.as_str(), subs[1].months), ("user2", 5));
+        }
+
         #[bench]
         fn bench_parse_line(b: &mut Bencher) {
             let line = "[2019-07-01 00:00:42 UTC] someuser: message";
@@ -200,3 +762,211 @@ pub mod overrustle {
         }
     }
 }
+
+pub mod twitch_irc {
+    use super::*;
+    use chrono::TimeZone;
+
+    const ACTION_PREFIX: &str = "\u{1}ACTION ";
+    const ACTION_SUFFIX: char = '\u{1}';
+
+    /// Finds value of IRCv3 tag with given key. `tags` should not include leading '@'.
+    /// Value is unescaped, and only copied if it contains escape sequences.
+    pub(crate) fn find_tag<'a>(tags: &'a str, key: &str) -> Option<Cow<'a, str>> {
+        tags.split(';').find_map(|tag| {
+            let mut kv = tag.splitn(2, '=');
+            if kv.next()? == key { Some(unescape_tag_value(kv.next().unwrap_or(""))) } else { None }
+        })
+    }
+
+    /// Reverses IRCv3 tag value escaping: `\:` is `;`, `\s` is space, `\\` is backslash, `\r` and
+    /// `\n` are CR and LF. Backslash before any other character is dropped, as is trailing one.
+    pub(crate) fn unescape_tag_value(value: &str) -> Cow<str> {
+        if !value.contains('\\') {
+            return Cow::Borrowed(value);
+        }
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(':') => unescaped.push(';'),
+                Some('s') => unescaped.push(' '),
+                Some('r') => unescaped.push('\r'),
+                Some('n') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => {}
+            }
+        }
+        Cow::Owned(unescaped)
+    }
+
+    /// Splits raw IRC line into tags, prefix, command and the rest of the line.
+    pub(crate) fn split_line(line: &str) -> Option<(&str, &str, &str, &str)> {
+        // According to Twitch IRC structure:
+        // @badge-info=;badges=;...;tmi-sent-ts=1561939242000 :nick!nick@nick.tmi.twitch.tv PRIVMSG #channel :text
+        let line = line.trim_end_matches('\r');
+        if !line.starts_with('@') {
+            return None;
+        }
+        let tags_end = line.find(' ')?;
+        let tags = &line[1..tags_end];
+
+        let rest = line[tags_end + 1..].trim_start_matches(' ');
+        if !rest.starts_with(':') {
+            return None;
+        }
+        let prefix_end = rest.find(' ')?;
+        let prefix = &rest[1..prefix_end];
+
+        let rest = &rest[prefix_end + 1..];
+        let (command, params) = match rest.find(' ') {
+            Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+            None => (rest, "")
+        };
+
+        Some((tags, prefix, command, params))
+    }
+
+    pub(crate) fn parse_timestamp(tags: &str) -> Option<DateTime<Utc>> {
+        let ms: i64 = find_tag(tags, "tmi-sent-ts")?.parse().ok()?;
+        Utc.timestamp_opt(ms / 1000, ((ms % 1000) * 1_000_000) as u32).single()
+    }
+
+    pub(crate) fn parse_metadata(tags: &str) -> MessageMetadata {
+        let subscriber_months = find_tag(tags, "badge-info")
+            .and_then(|info| info
+                .split(',')
+                .find(|b| b.starts_with("subscriber/"))
+                .and_then(|b| b["subscriber/".len()..].parse().ok()));
+        let non_empty = |s: &Cow<str>| !s.is_empty();
+
+        MessageMetadata {
+            badges: find_tag(tags, "badges").unwrap_or(Cow::Borrowed("")),
+            subscriber_months,
+            bits: find_tag(tags, "bits").and_then(|b| b.parse().ok()),
+            display_name: find_tag(tags, "display-name").filter(non_empty),
+            color: find_tag(tags, "color").filter(non_empty),
+            id: find_tag(tags, "id").filter(non_empty),
+            reply_parent: find_tag(tags, "reply-parent-msg-id").filter(non_empty),
+        }
+    }
+
+    pub(crate) fn parse_line_with_metadata(line: &str)
+        -> Option<(DateTime<Utc>, &str, &str, Option<MessageMetadata>)> {
+        let (tags, prefix, command, params) = split_line(line)?;
+        if command != "PRIVMSG" {
+            return None;
+        }
+
+        // nick!user@host -- nick is the login name of the user
+        let user = &prefix[..prefix.find('!')?];
+
+        // #channel :text
+        let message = &params[params.find(" :")? + 2..];
+
+        // `/me` messages are sent as CTCP ACTION: \x01ACTION text\x01
+        let message = if message.starts_with(ACTION_PREFIX) {
+            message[ACTION_PREFIX.len()..].trim_end_matches(ACTION_SUFFIX)
+        } else {
+            message
+        };
+
+        Some((parse_timestamp(tags)?, user, message, Some(parse_metadata(tags))))
+    }
+
+    pub fn parse_string(s: String) -> Messages {
+        // tmi-sent-ts is assigned by Twitch servers, so lines captured from IRC can
+        // be slightly out of order
+        Messages::from_string_with_metadata(s, parse_line_with_metadata, true)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        const LINE: &str = "@badge-info=subscriber/8;badges=subscriber/6,bits/100;color=#FF4500;\
+            display-name=SomeUser;emotes=;flags=;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;mod=0;\
+            room-id=1337;subscriber=1;tmi-sent-ts=1561939242000;turbo=0;user-id=1337;user-type= \
+            :someuser!someuser@someuser.tmi.twitch.tv PRIVMSG #channel :FeelsGoodMan WE ARE READY";
+
+        #[test]
+        fn test_parse_line() {
+            let messages = parse_string(LINE.to_string());
+            match messages.vec().first() {
+                Some(message) => {
+                    assert_eq!(message.user(), "someuser");
+                    assert_eq!(message.message(), "FeelsGoodMan WE ARE READY");
+                    assert_eq!(
+                        message.timestamp(),
+                        chrono::DateTime::<Utc>::from(
+                            humantime::parse_rfc3339_weak("2019-07-01 00:00:42").unwrap()
+                        )
+                    );
+                },
+                None => assert!(false, "Message should parse correctly")
+            }
+        }
+
+        #[test]
+        fn test_parse_metadata() {
+            let messages = parse_string(format!("{}\n{}", LINE, LINE.replace("bits/100", "moderator/1")));
+            assert_eq!(messages.vec().len(), 2);
+            let meta = messages.vec()[0].metadata().expect("Metadata should be present");
+            assert_eq!(meta.subscriber_months, Some(8));
+            assert_eq!(meta.display_name.as_deref(), Some("SomeUser"));
+            assert_eq!(meta.color.as_deref(), Some("#FF4500"));
+            assert_eq!(meta.id.as_deref(), Some("b34ccfc7-4977-403a-8a94-33c6bac34fb8"));
+            assert_eq!(meta.bits, None);
+            assert_eq!(meta.reply_parent, None);
+            assert_eq!(meta.badges().collect::<Vec<_>>(), vec![("subscriber", "6"), ("bits", "100")]);
+            assert!(messages.vec()[0].is_subscriber());
+            assert!(!messages.vec()[0].is_moderator());
+            assert!(messages.vec()[1].is_moderator());
+        }
+
+        #[test]
+        fn test_unescape_tags() {
+            assert_eq!(unescape_tag_value(r"a\sb\:c\\d\ne\x\"), "a b;c\\d\nex");
+            assert!(match unescape_tag_value("plain") { Cow::Borrowed(_) => true, _ => false });
+
+            let messages = parse_string(LINE.replace("display-name=SomeUser", r"display-name=Some\sUser\:"));
+            let meta = messages.vec()[0].metadata().expect("Metadata should be present");
+            assert_eq!(meta.display_name.as_deref(), Some("Some User;"));
+            // escaped values outlive the parser
+            drop(meta);
+            let merged = Messages::merge(vec![("channel".to_string(), messages)]);
+            assert_eq!(merged.vec()[0].metadata().unwrap().display_name.as_deref(), Some("Some User;"));
+        }
+
+        #[test]
+        fn test_parse_action() {
+            let line = "@badges=;color=;display-name=SomeUser;emotes=;id=1;tmi-sent-ts=1561939242000;user-type= \
+                :someuser!someuser@someuser.tmi.twitch.tv PRIVMSG #channel :\u{1}ACTION dances\u{1}\r";
+            let messages = parse_string(line.to_string());
+            match messages.vec().first() {
+                Some(message) => {
+                    assert_eq!(message.user(), "someuser");
+                    assert_eq!(message.message(), "dances");
+                },
+                None => assert!(false, "Message should parse correctly")
+            }
+        }
+
+        #[test]
+        fn test_skip_non_privmsg() {
+            let lines = "@emote-only=0;followers-only=-1;r9k=0;room-id=1337;slow=0;subs-only=0 \
+                :tmi.twitch.tv ROOMSTATE #channel\n:tmi.twitch.tv 001 justinfan123 :Welcome, GLHF!";
+            assert!(parse_string(lines.to_string()).vec().is_empty());
+        }
+
+        #[bench]
+        fn bench_parse_line(b: &mut Bencher) {
+            b.iter(|| parse_line_with_metadata(LINE))
+        }
+    }
+}
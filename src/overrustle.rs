@@ -2,65 +2,312 @@ extern crate test;
 
 use crate::message::*;
 use crate::util::*;
-use crate::chatlog::DailyChatLog;
+use crate::chatlog::{DailyChatLog, Chunks};
 
 use std::io;
 use std::result::Result;
 use std::path::PathBuf;
 use std::iter::Iterator;
-use std::fs::File;
+use std::io::{BufReader, BufRead, Read};
+use std::time;
+use std::collections::HashMap;
 
 use rayon::prelude::*;
-use chrono::{Date, Utc, NaiveDate, Duration};
+use chrono::{Date, DateTime, Datelike, Utc, NaiveDate, Duration};
+use serde::{Serialize, Deserialize};
 use scraper::{Html, Selector};
-use reqwest::Client;
+use reqwest::{Client, Response, StatusCode};
+use reqwest::header::{ETAG, LAST_MODIFIED, IF_NONE_MATCH, IF_MODIFIED_SINCE};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use crate::message::overrustle::parse_string;
+use crate::message::overrustle::{parse_string, parse_file, parse_line, parse_ban, parse_subscription};
+pub use crate::message::overrustle::{BanEvent, SubscriptionEvent};
+use crate::compression::Compression;
 
-const BASE_URL: &str = "https://overrustlelogs.net";
+pub const DEFAULT_BASE_URL: &str = "https://overrustlelogs.net";
+
+/// Name of the file in channel directory where index is persisted between syncs
+const MANIFEST_FILE: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+/// Represents an error occurred when syncing or loading OverRustle logs
+#[derive(Debug)]
+pub enum Error {
+    /// Request could not be sent or response could not be read
+    Network(reqwest::Error),
+    /// Server responded with non-success status code
+    HttpStatus { url: String, status: StatusCode },
+    /// Local file could not be read or written
+    Io(io::Error),
+    /// Date could not be extracted from URL or file name
+    DateParse { input: String, source: chrono::format::ParseError },
+    /// Page does not have the expected structure
+    HtmlStructure { url: String, reason: String },
+    /// Downloaded file does not look like a log file (e.g. it is an error page)
+    InvalidContent { url: String },
+    /// Requested date is not present in index
+    NotInIndex(Date<Utc>),
+    /// Requested date is present in index, but there is no local copy of it, e.g. because it
+    /// failed to download during sync, and data load mode doesn't allow downloading it now
+    NotCached(Date<Utc>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Error::Network(err) => write!(f, "network error: {}", err),
+            Error::HttpStatus { url, status } => write!(f, "HTTP {} for {}", status, url),
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::DateParse { input, source } => write!(f, "cannot parse date from '{}': {}", input, source),
+            Error::HtmlStructure { url, reason } => write!(f, "unexpected page structure at {}: {}", url, reason),
+            Error::InvalidContent { url } => write!(f, "{} is not a log file", url),
+            Error::NotInIndex(date) => write!(f, "{} is not in index", date),
+            Error::NotCached(date) => write!(f, "{} is not cached locally", date),
+        }
+    }
+}
+
+impl Error {
+    /// Whether retrying the operation which resulted in this error might succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Network(_) | Error::InvalidContent { .. } => true,
+            Error::HttpStatus { status, .. } =>
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::DateParse { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Network(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
 
 
 #[derive(Debug, Clone)]
 pub struct LogFileUrl {
     url: String,
     path: Option<PathBuf>,
-    date: Date<Utc>
+    date: Date<Utc>,
+    /// Known only for files downloaded by `OverRustleLogs` itself
+    info: Option<FileInfo>,
+}
+
+/// Kind of a log file OverRustle keeps for each month in addition to daily logs
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MonthlyLogKind {
+    /// Messages sent by the broadcaster
+    Broadcaster,
+    /// Subscription notifications, see `SubscriptionEvent`
+    Subscribers,
+    /// Bans and timeouts, see `BanEvent`
+    Bans,
+    /// All messages sent by a single user
+    User(String),
+}
+
+impl MonthlyLogKind {
+
+    /// Name of the file relative to month directory, without extension
+    fn file_stem(&self) -> String {
+        match self {
+            MonthlyLogKind::Broadcaster => "broadcaster".to_string(),
+            MonthlyLogKind::Subscribers => "subscribers".to_string(),
+            MonthlyLogKind::Bans => "bans".to_string(),
+            MonthlyLogKind::User(user) => format!("userlogs/{}", user),
+        }
+    }
+
+    fn from_file_stem(s: &str) -> Option<MonthlyLogKind> {
+        match s {
+            "broadcaster" => Some(MonthlyLogKind::Broadcaster),
+            "subscribers" => Some(MonthlyLogKind::Subscribers),
+            "bans" => Some(MonthlyLogKind::Bans),
+            _ => None
+        }
+    }
+
+}
+
+/// Monthly log file, such as list of bans for a month. Cached copies are stored in
+/// `<channel>/YYYY-MM/` directory.
+#[derive(Debug, Clone)]
+pub struct MonthlyLogUrl {
+    url: String,
+    path: Option<PathBuf>,
+    /// The first day of the month
+    month: Date<Utc>,
+    kind: MonthlyLogKind,
+}
+
+impl MonthlyLogUrl {
+
+    pub fn month(&self) -> Date<Utc> {
+        self.month
+    }
+
+    pub fn kind(&self) -> &MonthlyLogKind {
+        &self.kind
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+}
+
+/// Information about local copy of a log file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileInfo {
+    /// Size of uncompressed data
+    pub size: u64,
+    /// CRC32 of uncompressed data
+    pub checksum: u32,
+    /// When the file was downloaded
+    pub fetched: DateTime<Utc>,
+    /// `ETag` header of the response the file was downloaded from
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` header of the response the file was downloaded from
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+impl FileInfo {
+
+    fn new((size, checksum): (u64, u32)) -> FileInfo {
+        FileInfo { size, checksum, fetched: Utc::now(), etag: None, last_modified: None }
+    }
+
+    /// Compute information for existing local file
+    fn from_file(path: &PathBuf) -> io::Result<FileInfo> {
+        let mut reader = ChecksumReader::new(Compression::from_path(path).open(path)?);
+        io::copy(&mut reader, &mut io::sink())?;
+        let fetched = DateTime::<Utc>::from(std::fs::metadata(path)?.modified()?);
+        Ok(FileInfo { fetched, ..FileInfo::new(reader.finish()) })
+    }
+
+    fn has_same_content(&self, other: &FileInfo) -> bool {
+        self.size == other.size && self.checksum == other.checksum
+    }
+
+}
+
+/// Persisted form of `LogFileUrl`
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    date: NaiveDate,
+    url: String,
+    /// File name relative to channel directory
+    path: Option<String>,
+    info: Option<FileInfo>,
+}
+
+/// Persisted form of `MonthlyLogUrl`. User logs are not indexed, so they are never persisted.
+#[derive(Debug, Serialize, Deserialize)]
+struct MonthlyManifestEntry {
+    month: NaiveDate,
+    kind: MonthlyLogKind,
+    url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    entries: Vec<ManifestEntry>,
+    #[serde(default)]
+    monthly: Vec<MonthlyManifestEntry>,
 }
 
 impl LogFileUrl {
 
-    pub fn from_overrustle_url(url: &str) -> Result<LogFileUrl, chrono::format::ParseError> {
+    pub fn from_overrustle_url(base_url: &str, url: &str) -> Result<LogFileUrl, Error> {
         let date_str = url.rsplitn(2, '/').next().unwrap();
-        let parsing_result = NaiveDate::parse_from_str(date_str, "%Y-%m-%d");
-        match parsing_result {
-            Ok(date) => {
-                let date = Date::<Utc>::from_utc(date, Utc);
-                Ok(LogFileUrl { url: format!("{}{}.txt", BASE_URL, url), path: None, date })
-            },
-            Err(err) => Err(err)
-        }
+        let date = parse_date(date_str, "%Y-%m-%d")?;
+        Ok(LogFileUrl { url: format!("{}{}.txt", base_url, url), path: None, date, info: None })
     }
 
-    pub fn from_local_path(channel: &String, path: PathBuf) -> Option<LogFileUrl> {
-        let date_str = path.file_name().unwrap_or("".as_ref()).to_str().unwrap();
-        let date = Date::<Utc>::from_utc(NaiveDate::parse_from_str(date_str, "%Y-%m-%d.txt").ok()?, Utc);
+    /// Recognizes both plain (`YYYY-MM-DD.txt`) and compressed (`YYYY-MM-DD.txt.gz`,
+    /// `YYYY-MM-DD.txt.zst`) files.
+    pub fn from_local_path(base_url: &str, channel: &String, path: PathBuf) -> Result<LogFileUrl, Error> {
+        let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+        let date_str = Compression::from_path(&path).strip_extension(file_name);
+        let date = parse_date(date_str, "%Y-%m-%d.txt")?;
         let month_name_year = date.format("%B%%20%Y").to_string();
-        let url = format!("{}/{}%20chatlog/{}/{}.txt", BASE_URL, capitalized(channel),
+        let url = format!("{}/{}%20chatlog/{}/{}", base_url, capitalized(channel),
                           capitalized(&month_name_year), date_str);
-        Some(LogFileUrl { url, path: Some(path), date })
+        Ok(LogFileUrl { url, path: Some(path), date, info: None })
     }
 
     pub fn detect_local(&mut self, root_path: &PathBuf) -> bool {
-        let full_path = root_path.join(self.date.format("%Y-%m-%d.txt").to_string());
-        self.path = if full_path.is_file() { Some(full_path) } else { None };
+        self.path = find_file_path(root_path, &self.date);
         self.path.is_some()
     }
 
+    pub fn date(&self) -> Date<Utc> {
+        self.date
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    pub fn info(&self) -> Option<&FileInfo> {
+        self.info.as_ref()
+    }
+
+    fn to_manifest_entry(&self) -> ManifestEntry {
+        ManifestEntry {
+            date: self.date.naive_utc(),
+            url: self.url.clone(),
+            path: self.path.as_ref()
+                .and_then(|p| p.file_name())
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_string()),
+            info: self.info.clone(),
+        }
+    }
+
+    fn from_manifest_entry(root_path: &PathBuf, entry: ManifestEntry) -> LogFileUrl {
+        LogFileUrl {
+            url: entry.url,
+            path: entry.path.map(|p| root_path.join(p)),
+            date: Date::from_utc(entry.date, Utc),
+            info: entry.info,
+        }
+    }
+
 }
 
 /// Data load mode for
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataLoadMode {
     /// Data for each index date will be loaded only on demand and won't be cached
     Remote,
@@ -111,136 +358,653 @@ impl ToString for DataLoadMode {
     }
 }
 
-pub enum SlideError {
-    InvalidTimeInterval,
-    NotEnoughDataInIndex,
+/// Controls how requests to OverRustle are made
+#[derive(Debug, Clone)]
+pub struct DownloadPolicy {
+    /// Number of retries after the first failed attempt. Only transient errors are retried
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each subsequent attempt
+    pub backoff: time::Duration,
+    /// Maximum number of requests in flight
+    pub max_concurrency: usize,
+    /// Maximum number of requests per second, 0 means unlimited
+    pub requests_per_second: u32,
+}
+
+impl Default for DownloadPolicy {
+    fn default() -> Self {
+        DownloadPolicy {
+            retries: 3,
+            backoff: time::Duration::from_secs(1),
+            max_concurrency: 8,
+            requests_per_second: 10,
+        }
+    }
+}
+
+/// HTTP client which follows `DownloadPolicy`
+#[derive(Debug)]
+struct Fetcher {
+    client: Client,
+    policy: DownloadPolicy,
+    limiter: RateLimiter,
+    /// Pool limited to `max_concurrency` threads, or why it could not be started
+    pool: Result<rayon::ThreadPool, rayon::ThreadPoolBuildError>,
+}
+
+impl Fetcher {
+
+    fn new(policy: DownloadPolicy) -> Fetcher {
+        let limiter = RateLimiter::new(policy.requests_per_second);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(policy.max_concurrency).build();
+        Fetcher { client: Client::new(), policy, limiter, pool }
+    }
+
+    /// Run parallel operation on a thread pool limited to `max_concurrency` threads
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> Result<R, Error> {
+        let pool = self.pool.as_ref().map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        Ok(pool.install(op))
+    }
+
+    /// Send GET request and handle successful response with `handle`, retrying with
+    /// exponential backoff if either of them fails with transient error.
+    ///
+    /// If `previous` is given, request is made conditional on the resource being changed
+    /// since then, and `handle` should also expect `304 Not Modified` responses.
+    fn get<T, F: Fn(Response) -> Result<T, Error>>(
+        &self, url: &String, previous: Option<&FileInfo>, handle: F
+    ) -> Result<T, Error> {
+        let mut delay = self.policy.backoff;
+        let mut attempt = 0;
+        loop {
+            self.limiter.wait();
+            let mut request = self.client.get(url);
+            if let Some(previous) = previous {
+                if let Some(etag) = previous.etag.as_ref() {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = previous.last_modified.as_ref() {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            let result = request.send()
+                .map_err(Error::from)
+                .and_then(|response| {
+                    let not_modified = previous.is_some() && response.status() == StatusCode::NOT_MODIFIED;
+                    if response.status() != StatusCode::OK && !not_modified {
+                        Err(Error::HttpStatus { url: url.clone(), status: response.status() })
+                    } else {
+                        handle(response)
+                    }
+                });
+            match result {
+                Err(ref err) if err.is_transient() && attempt < self.policy.retries => {
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+
+    fn get_text(&self, url: &String) -> Result<String, Error> {
+        self.get(url, None, |mut response| Ok(response.text()?))
+    }
+
+    /// Same as `get_text`, but also checks that response looks like a log file
+    fn get_log(&self, url: &String) -> Result<String, Error> {
+        self.get(url, None, |mut response| {
+            let text = response.text()?;
+            if is_log_shaped(text.lines().next().unwrap_or("")) {
+                Ok(text)
+            } else {
+                Err(Error::InvalidContent { url: url.clone() })
+            }
+        })
+    }
+
+    /// Download log file into `path`, checking that response looks like a log file. Data is
+    /// first written into a temporary file, so `path` is never left partially written.
+    ///
+    /// If information about `previous` download is given, returns None when remote file hasn't
+    /// changed since then, in which case nothing is written.
+    fn download_log(
+        &self, url: &String, path: &PathBuf, compression: Compression, previous: Option<&FileInfo>
+    ) -> Result<Option<FileInfo>, Error> {
+        self.get(url, previous, |response| {
+            if response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            let header = |name| response.headers()
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string());
+            let etag = header(ETAG);
+            let last_modified = header(LAST_MODIFIED);
+
+            let mut reader = BufReader::new(response);
+            let mut first_line = String::new();
+            reader.read_line(&mut first_line)?;
+            if !is_log_shaped(&first_line) {
+                return Err(Error::InvalidContent { url: url.clone() });
+            }
+            let mut reader = ChecksumReader::new(io::Cursor::new(first_line).chain(reader));
+            let mut info = None;
+            compression.write_atomic_if(path, &mut reader, |reader| {
+                let downloaded = FileInfo { etag, last_modified, ..FileInfo::new(reader.finish()) };
+                // server doesn't support conditional requests, but contents are the same anyway
+                if previous.map_or(false, |p| p.has_same_content(&downloaded)) {
+                    return false;
+                }
+                info = Some(downloaded);
+                true
+            })?;
+            Ok(info)
+        })
+    }
+
 }
 
 #[derive(Debug)]
 pub struct OverRustleLogs {
     root_path: PathBuf,
     channel: String,
-    client: Client,
+    /// Root URL of OverRustle or its mirror, without trailing slash
+    base_url: String,
+    fetcher: Fetcher,
     index: Vec<LogFileUrl>,
+    /// Broadcaster, subscribers and bans files for each month
+    monthly_index: Vec<MonthlyLogUrl>,
     mode: DataLoadMode,
+    /// Compression used for newly cached files. Files are read with whatever compression they have
+    compression: Compression,
+    /// Dates which could not be downloaded during the last sync
+    failed: Vec<(Date<Utc>, Error)>,
+    /// Whether persisted index should be ignored when syncing
+    full_resync: bool,
+    /// Days this recent are considered incomplete and are downloaded again on each sync
+    refresh_horizon: Duration,
+    /// Whether index has changed since it was last persisted
+    dirty: bool,
+    /// Why persisted index could not be used during the last sync
+    manifest_error: Option<Error>,
 }
 
 impl OverRustleLogs {
 
     pub fn new(root_path: PathBuf, channel: String, mode: DataLoadMode) -> OverRustleLogs {
         OverRustleLogs {
-            root_path, channel, client: Client::new(), index: Vec::new(), mode
+            root_path, channel, base_url: DEFAULT_BASE_URL.to_string(),
+            fetcher: Fetcher::new(DownloadPolicy::default()), index: Vec::new(), monthly_index: Vec::new(), mode,
+            compression: Compression::None, failed: Vec::new(), full_resync: false,
+            refresh_horizon: Duration::days(2), dirty: false, manifest_error: None
         }
     }
 
-    pub fn make_and_sync(root_path: PathBuf, channel: String, mode: DataLoadMode) -> OverRustleLogs {
+    /// Use OverRustle mirror at given URL instead of the original site
+    pub fn with_base_url(mut self, base_url: String) -> OverRustleLogs {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Set compression used for caching files
+    pub fn with_compression(mut self, compression: Compression) -> OverRustleLogs {
+        self.compression = compression;
+        self
+    }
+
+    /// Set retry and rate limiting policy for requests
+    pub fn with_download_policy(mut self, policy: DownloadPolicy) -> OverRustleLogs {
+        self.fetcher = Fetcher::new(policy);
+        self
+    }
+
+    /// Set how recent days should be to be downloaded again on each sync. OverRustle keeps
+    /// appending to the current day, and might lag behind with the previous one.
+    pub fn with_refresh_horizon(mut self, refresh_horizon: Duration) -> OverRustleLogs {
+        self.refresh_horizon = refresh_horizon;
+        self
+    }
+
+    /// Ignore persisted index and scrape all the months again on next sync
+    pub fn with_full_resync(mut self, full_resync: bool) -> OverRustleLogs {
+        self.full_resync = full_resync;
+        self
+    }
+
+    /// Dates which could not be downloaded during the last sync, along with the last error
+    /// occurred for each of them. These dates stay in index, but have no local data.
+    pub fn failed_downloads(&self) -> &[(Date<Utc>, Error)] {
+        &self.failed
+    }
+
+    /// Error which prevented persisted index from being used during the last sync, if any.
+    /// Such index is ignored, so all the months are scraped again (or, in local mode, file
+    /// information has to be checked again).
+    pub fn manifest_error(&self) -> Option<&Error> {
+        self.manifest_error.as_ref()
+    }
+
+    pub fn make_and_sync(root_path: PathBuf, channel: String, mode: DataLoadMode) -> Result<OverRustleLogs, Error> {
         let mut o = OverRustleLogs::new(root_path, channel, mode);
-        o.sync().expect("Could not sync logs");
-        o
+        o.sync()?;
+        Ok(o)
     }
 
-    fn detect_local_files(&self) -> io::Result<Vec<LogFileUrl>> {
+    fn detect_local_files(&mut self) -> Result<(Vec<LogFileUrl>, Vec<MonthlyLogUrl>), Error> {
         let root_path = self.root_path.canonicalize()?.join(&self.channel);
 
-        let mut index = if !root_path.is_dir() {
+        let mut index = Vec::new();
+        let mut monthly_index = Vec::new();
+        if !root_path.is_dir() {
             std::fs::create_dir_all(&root_path)?;
-            Vec::new()
         } else {
-            std::fs::read_dir(&root_path)?
-                .filter_map(|path| {
-                    let path = path.expect("Cannot get Path from DirEntry").path();
-                    LogFileUrl::from_local_path(&self.channel, path)
-                })
-                .collect::<Vec<_>>()
+            for entry in std::fs::read_dir(&root_path)? {
+                let path = entry?.path();
+                // directories named after months contain monthly logs
+                if let Some(month) = parse_month_dir(&path) {
+                    for kind in &[MonthlyLogKind::Broadcaster, MonthlyLogKind::Subscribers, MonthlyLogKind::Bans] {
+                        if let Some(path) = find_monthly_file_path(&root_path, &month, kind) {
+                            let url = make_monthly_url(&self.base_url, &self.channel, &month, kind);
+                            monthly_index.push(MonthlyLogUrl { url, path: Some(path), month, kind: kind.clone() });
+                        }
+                    }
+                }
+                // files not named after dates are not logs, skip them
+                else if let Ok(url) = LogFileUrl::from_local_path(&self.base_url, &self.channel, path) {
+                    index.push(url);
+                }
+            }
+        }
+        monthly_index.sort_by_key(|m| m.month);
+
+        // the same date can be stored with different compression (e.g. after interrupted
+        // `recompress`), keep the one with configured compression, or the first one of `ALL`
+        index.sort_unstable_by_key(|l| {
+            let compression = l.path.as_ref().map_or(self.compression, |p| Compression::from_path(p));
+            (l.date, compression != self.compression, Compression::ALL.iter().position(|c| *c == compression))
+        });
+        index.dedup_by_key(|l| l.date);
+
+        // local mode should work even if persisted index is broken, it is only used for file info
+        let (known, _) = self.load_manifest_or_record_error();
+        for l in index.iter_mut() {
+            if let Ok(idx) = known.binary_search_by_key(&l.date, |k| k.date) {
+                let k = &known[idx];
+                if k.path.as_ref().and_then(|p| p.file_name()) == l.path.as_ref().and_then(|p| p.file_name()) {
+                    l.url = k.url.clone();
+                    l.info = k.info.clone();
+                }
+            }
+        }
+
+        Ok((index, monthly_index))
+    }
+
+    /// Scrapes OverRustle for the dates missing in persisted index. The last month in index is
+    /// always scraped again, as new days might have been added to it since the previous sync.
+    fn detect_remote_files(&mut self) -> Result<(Vec<LogFileUrl>, Vec<MonthlyLogUrl>), Error> {
+        let root_path = self.root_path.join(&self.channel);
+
+        let (known, known_monthly) = if self.full_resync {
+            (Vec::new(), Vec::new())
+        } else {
+            // broken index is not worth failing for, everything will be scraped again instead
+            self.load_manifest_or_record_error()
         };
+        let since = known.last().map(|l| first_day_of_month(&l.date));
+        let (mut index, rescraped): (Vec<_>, Vec<_>) = known
+            .into_iter()
+            .partition(|l| since.map_or(true, |since| l.date < since));
+        let mut monthly_index = known_monthly
+            .into_iter()
+            .filter(|m| since.map_or(true, |since| m.month < since))
+            .collect::<Vec<_>>();
+        let (days, months) = get_all_urls_for_channel(&self.fetcher, &self.base_url, &self.channel, since)?;
+        // keep what we know about files which are scraped again, so they can be refreshed
+        // with conditional requests
+        let mut rescraped = rescraped.into_iter().map(|l| (l.date, l)).collect::<HashMap<_, _>>();
+        index.extend(days.into_iter().map(|mut l| {
+            if let Some(known) = rescraped.remove(&l.date).filter(|known| known.url == l.url) {
+                l.path = known.path;
+                l.info = known.info;
+            }
+            l
+        }));
+        monthly_index.extend(months);
 
+        for m in monthly_index.iter_mut() {
+            m.path = find_monthly_file_path(&root_path, &m.month, &m.kind);
+        }
+        monthly_index.sort_by_key(|m| m.month);
+
+        for l in index.iter_mut() {
+            let mut path = find_file_path(&root_path, &l.date);
+            if let Some(p) = path.as_ref() {
+                // days which were downloaded empty are recorded in index, other empty files
+                // don't count, e.g. ones left behind by earlier versions
+                let downloaded_empty = l.path.as_ref() == Some(p) && l.info.as_ref().map_or(false, |i| i.size == 0);
+                if std::fs::metadata(p)?.len() == 0 && !downloaded_empty {
+                    path = None;
+                }
+            }
+            // persisted information is only valid if the file is still the same
+            if path != l.path {
+                l.info = None;
+            }
+            l.path = path;
+        }
+        index.sort_unstable_by_key(|l| l.date);
+        Ok((index, monthly_index))
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.root_path.join(&self.channel).join(MANIFEST_FILE)
+    }
+
+    /// Same as `load_manifest`, but an error results in empty index and is kept for `manifest_error`
+    fn load_manifest_or_record_error(&mut self) -> (Vec<LogFileUrl>, Vec<MonthlyLogUrl>) {
+        self.load_manifest().unwrap_or_else(|err| {
+            self.manifest_error = Some(err);
+            (Vec::new(), Vec::new())
+        })
+    }
+
+    /// Load persisted index, or return empty index if there is none
+    fn load_manifest(&self) -> Result<(Vec<LogFileUrl>, Vec<MonthlyLogUrl>), Error> {
+        let path = self.manifest_path();
+        if !path.is_file() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(std::fs::File::open(&path)?))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if manifest.version != MANIFEST_VERSION {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let root_path = self.root_path.join(&self.channel);
+        let mut index = manifest.entries
+            .into_iter()
+            .map(|e| LogFileUrl::from_manifest_entry(&root_path, e))
+            .collect::<Vec<_>>();
         index.sort_unstable_by_key(|l| l.date);
+        let mut monthly_index = manifest.monthly
+            .into_iter()
+            .map(|e| MonthlyLogUrl { url: e.url, path: None, month: Date::from_utc(e.month, Utc), kind: e.kind })
+            .collect::<Vec<_>>();
+        monthly_index.sort_by_key(|m| m.month);
+        Ok((index, monthly_index))
+    }
 
-        Ok(index)
+    /// Persist current index into channel directory, so the next sync doesn't have to scrape it again
+    pub fn save_manifest(&self) -> Result<(), Error> {
+        self.write_manifest(&self.index, &self.monthly_index)
     }
 
-    fn detect_remote_files(&self) -> io::Result<Vec<LogFileUrl>> {
+    /// Persist index if it has changed since the last sync, e.g. after files were cached by `load`.
+    /// This also happens when logs are dropped, but errors are lost then.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            self.save_manifest()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn write_manifest(&self, index: &[LogFileUrl], monthly_index: &[MonthlyLogUrl]) -> Result<(), Error> {
+        let path = self.manifest_path();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            entries: index.iter().map(|l| l.to_manifest_entry()).collect(),
+            monthly: monthly_index
+                .iter()
+                .map(|m| MonthlyManifestEntry { month: m.month.naive_utc(), kind: m.kind.clone(), url: m.url.clone() })
+                .collect(),
+        };
+        let tmp_path = path.with_extension("part");
+        let file = io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+        serde_json::to_writer(file, &manifest)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Entries of the current index
+    pub fn index(&self) -> &[LogFileUrl] {
+        &self.index
+    }
+
+    /// Monthly logs of the current index. User logs are not included, see `list_users`.
+    pub fn monthly_index(&self) -> &[MonthlyLogUrl] {
+        &self.monthly_index
+    }
+
+    /// Load monthly log of given kind for the month `month` belongs to. Cached copies are only
+    /// used for months which ended before refresh horizon, as the rest might still be changing.
+    pub fn load_monthly(&mut self, month: &Date<Utc>, kind: &MonthlyLogKind) -> Result<Messages, Error> {
+        let month = first_day_of_month(month);
         let root_path = self.root_path.join(&self.channel);
-        let mut index = get_all_urls_for_channel(&self.client, &self.channel);
-        index
-            .iter_mut()
-            .for_each(|l| {
-                let path = make_file_path(&root_path, &l.date);
-                if path.is_file() {
-                    let meta = std::fs::metadata(&path).expect("Cannot get metadata for file");
-                    if meta.len() > 0 { // only count non-empty files
-                        l.path = Some(path)
+        let (url, path) = match self.monthly_index.iter().find(|m| m.month == month && m.kind == *kind) {
+            Some(m) => (m.url.clone(), m.path.clone()),
+            // user logs are not indexed, but their location is known anyway
+            None => match kind {
+                MonthlyLogKind::User(_) => (
+                    make_monthly_url(&self.base_url, &self.channel, &month, kind),
+                    find_monthly_file_path(&root_path, &month, kind)
+                ),
+                _ => return Err(Error::NotInIndex(month))
+            }
+        };
+        let complete = first_day_of_next_month(&month) <= Utc::today() - self.refresh_horizon;
+
+        match (&self.mode, path) {
+            (DataLoadMode::Remote, _) => Ok(parse_string(self.fetcher.get_text(&url)?)),
+            (DataLoadMode::Local, Some(path)) => Ok(read_file(&path)?),
+            (DataLoadMode::Local, None) => Err(Error::NotCached(month)),
+            (_, Some(ref path)) if complete => Ok(read_file(path)?),
+            (mode, old_path) => {
+                let data = self.fetcher.get_log(&url)?;
+                if let DataLoadMode::RemoteAndCache | DataLoadMode::PrefetchAndCache = mode {
+                    let path = make_monthly_file_path(&root_path, &month, kind, self.compression);
+                    std::fs::create_dir_all(path.parent().unwrap())?;
+                    self.compression.write_atomic(&path, &mut data.as_bytes())?;
+                    // file might have been cached with different compression before
+                    if let Some(old_path) = old_path.filter(|p| *p != path) {
+                        std::fs::remove_file(old_path)?;
+                    }
+                    if let Some(m) = self.monthly_index.iter_mut().find(|m| m.month == month && m.kind == *kind) {
+                        m.path = Some(path);
                     }
                 }
-            });
-        index.sort_unstable_by_key(|l| l.date);
-        Ok(index)
+                Ok(parse_string(data))
+            }
+        }
+    }
+
+    /// Load messages sent by the broadcaster during the month
+    pub fn load_broadcaster(&mut self, month: &Date<Utc>) -> Result<Messages, Error> {
+        self.load_monthly(month, &MonthlyLogKind::Broadcaster)
+    }
+
+    /// Load messages sent by `user` during the month
+    pub fn load_user_log(&mut self, month: &Date<Utc>, user: &str) -> Result<Messages, Error> {
+        self.load_monthly(month, &MonthlyLogKind::User(user.to_string()))
+    }
+
+    /// Load bans and timeouts issued during the month
+    pub fn load_bans(&mut self, month: &Date<Utc>) -> Result<Vec<BanEvent>, Error> {
+        let messages = self.load_monthly(month, &MonthlyLogKind::Bans)?;
+        Ok(messages.vec().iter().filter_map(parse_ban).collect())
+    }
+
+    /// Load subscriptions made during the month
+    pub fn load_subscriptions(&mut self, month: &Date<Utc>) -> Result<Vec<SubscriptionEvent>, Error> {
+        let messages = self.load_monthly(month, &MonthlyLogKind::Subscribers)?;
+        Ok(messages.vec().iter().filter_map(parse_subscription).collect())
     }
 
-    fn download_missing_files(&mut self) -> io::Result<()> {
-        let missing_count = self.index.iter().filter(|l| l.path.is_none()).count();
-        let bar = make_progress_bar(missing_count);
+    /// List users who have user logs for the month. In local mode only cached user logs are listed.
+    pub fn list_users(&self, month: &Date<Utc>) -> Result<Vec<String>, Error> {
+        let month = first_day_of_month(month);
+        let mut users = match self.mode {
+            DataLoadMode::Local => {
+                let path = self.root_path.join(&self.channel).join(month.format("%Y-%m").to_string()).join("userlogs");
+                if !path.is_dir() {
+                    return Ok(Vec::new());
+                }
+                let mut users = Vec::new();
+                for entry in std::fs::read_dir(&path)? {
+                    let path = entry?.path();
+                    let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+                    let stem = Compression::from_path(&path).strip_extension(file_name);
+                    if stem.ends_with(".txt") {
+                        users.push(stem.trim_end_matches(".txt").to_string());
+                    }
+                }
+                users
+            },
+            _ => {
+                let url = format!("{}/userlogs", make_month_url(&self.base_url, &self.channel, &month));
+                select_urls(&self.fetcher, &url)?
+                    .into_iter()
+                    .filter_map(|href| href.rsplitn(2, '/').next().map(|u| u.to_string()))
+                    .collect()
+            }
+        };
+        users.sort_unstable();
+        users.dedup();
+        Ok(users)
+    }
+
+    /// Download files which are missing locally, and refresh recent ones. Recent files are only
+    /// rewritten if they have actually changed.
+    fn download_missing_files(&mut self) -> Result<(), Error> {
+        let refresh_since = Utc::today() - self.refresh_horizon;
+        let needs_download = |l: &LogFileUrl| l.path.is_none() || l.date >= refresh_since;
+
+        let bar = make_progress_bar(self.index.iter().filter(|l| needs_download(l)).count());
         let root_path = &self.root_path.join(&self.channel);
         if !root_path.is_dir() {
             std::fs::create_dir_all(root_path)?;
         }
-        let client = &self.client;
-        let today = Utc::today();
+        let fetcher = &self.fetcher;
+        let compression = self.compression;
 
-        self.index
+        let index = &mut self.index;
+        let failed = fetcher.install(|| index
             .par_iter_mut()
-            .for_each(|l: &mut LogFileUrl| {
-                // TODO 14 days should be parameterized
-                if l.path.is_none() || l.date >= today - Duration::days(2) {
-                    let path = make_file_path(root_path, &l.date);
-
-                    // NOTE this doesn't work for overrustle as they don't send content-length
-                    // // check file sizes
-                    // if path.exists() {
-                    //     let local_size = std::fs::metadata(&path).expect("Cannot get metadata for file").len();
-                    //     match client.head(&l.url).send() {  
-                    //         Ok(resp) => {
-                    //             let remote_size = resp.content_length().unwrap_or(0);
-                    //             if local_size == remote_size {
-                    //                 // we don't need to download files if they are identical
-                    //                 bar.inc(1);
-                    //                 return;
-                    //             }
-                    //         }
-                    //         Err(err) => {
-                    //             // we silently discard path if unable to do HEAD against resource
-                    //             // TODO log error properly?
-                    //             eprintln!("ERR: cannot fetch metadata for remote file {:?}", err);
-                    //             bar.inc(1);
-                    //             return;
-                    //         }
-                    //     }
-                    // }
-                    // eprintln!("INF: downloading remote file {:?}", path);
-                    if let Ok(mut res) = client.get(&l.url).send() {
-                        let mut file = File::create(&path).expect("Unable to create local file");
-                        std::io::copy(&mut res, &mut file).expect("Unable to write to local file");
+            .filter(|l| needs_download(l))
+            .filter_map(|l: &mut LogFileUrl| {
+                let path = make_file_path(root_path, &l.date, compression);
+                let result = (|| {
+                    // files we didn't download ourselves have to be checked the hard way
+                    if l.info.is_none() {
+                        if let Some(old_path) = l.path.as_ref() {
+                            l.info = Some(FileInfo::from_file(old_path)?);
+                        }
+                    }
+                    let info = match fetcher.download_log(&l.url, &path, compression, l.info.as_ref())? {
+                        Some(info) => info,
+                        None => return Ok(())
+                    };
+                    // file might have been cached with different compression before
+                    if let Some(old_path) = l.path.as_ref().filter(|p| **p != path) {
+                        std::fs::remove_file(old_path)?;
                     }
                     l.path = Some(path);
-                    bar.inc(1);
-                }
-            });
+                    l.info = Some(info);
+                    Ok(())
+                })();
+                bar.inc(1);
+                result.err().map(|err| (l.date, err))
+            })
+            .collect::<Vec<_>>()
+        )?;
 
+        bar.finish();
+        self.failed = failed;
+        self.failed.sort_unstable_by_key(|(date, _)| *date);
         Ok(())
     }
 
-    pub fn sync(&mut self) -> io::Result<()> {
+    /// Rewrite all local files in index using given compression. Files which are already
+    /// compressed this way are left untouched.
+    pub fn recompress(&mut self, compression: Compression) -> Result<(), Error> {
+        let bar = make_progress_bar(self.index.len());
+        let root_path = self.root_path.join(&self.channel);
+
+        let result = self.index
+            .par_iter_mut()
+            .map(|l: &mut LogFileUrl| {
+                bar.inc(1);
+                let old_path = match l.path.as_ref() {
+                    Some(path) if Compression::from_path(path) != compression => path.clone(),
+                    _ => return Ok(())
+                };
+                let new_path = make_file_path(&root_path, &l.date, compression);
+                Compression::from_path(&old_path)
+                    .open(&old_path)
+                    .and_then(|mut reader| compression.write_atomic(&new_path, &mut reader))?;
+                std::fs::remove_file(&old_path)?;
+                l.path = Some(new_path);
+                Ok(())
+            })
+            .collect::<io::Result<()>>();
+
+        bar.finish();
+        result?;
+        self.compression = compression;
+        if !self.manifest_path().is_file() {
+            return Ok(());
+        }
+        match self.mode {
+            // local index only has files found on disk, so it can't replace persisted one,
+            // only entries of these files are updated
+            DataLoadMode::Local => {
+                let (mut known, known_monthly) = self.load_manifest()?;
+                for k in known.iter_mut() {
+                    if let Ok(idx) = self.index.binary_search_by_key(&k.date, |l| l.date) {
+                        k.path = self.index[idx].path.clone();
+                        k.info = self.index[idx].info.clone();
+                    }
+                }
+                self.write_manifest(&known, &known_monthly)
+            },
+            _ => self.save_manifest()
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
         self.index.clear();
+        self.monthly_index.clear();
+        self.failed.clear();
+        self.manifest_error = None;
         match self.mode {
-            DataLoadMode::Remote | DataLoadMode::RemoteAndCache => {
-                self.index.extend(self.detect_remote_files()?);
+            DataLoadMode::Remote => {
+                let (index, monthly_index) = self.detect_remote_files()?;
+                self.index = index;
+                self.monthly_index = monthly_index;
+            },
+            DataLoadMode::RemoteAndCache => {
+                let (index, monthly_index) = self.detect_remote_files()?;
+                self.index = index;
+                self.monthly_index = monthly_index;
+                self.save_manifest()?;
+                self.dirty = false;
             },
             DataLoadMode::Local => {
-                self.index.extend(self.detect_local_files()?);
+                let (index, monthly_index) = self.detect_local_files()?;
+                self.index = index;
+                self.monthly_index = monthly_index;
             },
             DataLoadMode::Prefetch | DataLoadMode::PrefetchAndCache => {
-                self.index.extend(self.detect_remote_files()?);
+                let (index, monthly_index) = self.detect_remote_files()?;
+                self.index = index;
+                self.monthly_index = monthly_index;
                 self.download_missing_files()?;
+                self.save_manifest()?;
+                self.dirty = false;
             }
         }
         Ok(())
@@ -248,6 +1012,13 @@ impl OverRustleLogs {
 
 }
 
+impl Drop for OverRustleLogs {
+    fn drop(&mut self) {
+        // nowhere to report errors to, next sync will have to check files the hard way
+        let _ = self.flush();
+    }
+}
+
 impl Display for OverRustleLogs {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let (n_local_files, local_files_size) = self.index
@@ -261,10 +1032,11 @@ impl Display for OverRustleLogs {
 
         write!(
             f,
-            "ChannelLogs {{ {channel} @ data_load_mode = {mode:?} ; local_path = {path:?} ; \
+            "ChannelLogs {{ {channel} @ data_load_mode = {mode:?} ; base_url = {base_url} ; local_path = {path:?} ; \
              URLs in index = {index_size} ; Local files in index = {local_count} ; Total size on disk = {size} }}",
             channel = self.channel,
             mode = self.mode,
+            base_url = self.base_url,
             path = self.root_path,
             index_size = self.index.len(),
             local_count = n_local_files,
@@ -283,105 +1055,222 @@ impl DailyChatLog for OverRustleLogs {
         }
     }
 
-    fn load(&mut self, date: &Date<Utc>) -> Option<Messages> {
-        // TODO proper error handling
-        let idx = self.index.binary_search_by_key(date, |l| l.date).map_err(|_| ()).ok()?;
-        let entry = &mut self.index[idx];
+    type Error = Error;
+
+    fn contains(&self, date: &Date<Utc>) -> bool {
+        self.index.binary_search_by_key(date, |l| l.date).is_ok()
+    }
 
-        let read_path = |path: &PathBuf|
-            std::fs::read_to_string(path).map_err(|_| ());
+    fn load(&mut self, date: &Date<Utc>) -> Result<Messages, Error> {
+        let idx = self.index.binary_search_by_key(date, |l| l.date).map_err(|_| Error::NotInIndex(*date))?;
+        let entry = &mut self.index[idx];
 
-        let res = match self.mode {
+        match self.mode {
             DataLoadMode::Remote => {
                 // simply get data from network
-                get_text(&self.client, &entry.url).map_err(|_| ())
+                Ok(parse_string(self.fetcher.get_text(&entry.url)?))
             },
             DataLoadMode::RemoteAndCache | DataLoadMode::PrefetchAndCache => {
                 match entry.path.as_ref() {
                     // cache hit, read from path
-                    Some(path) => read_path(&path),
+                    Some(path) => Ok(read_file(&path)?),
                     // cache miss, need to download data and save into fs
                     None => {
-                        let data = get_text(&self.client, &entry.url).map_err(|_| ()).ok()?;
-                        let path = make_file_path(&self.root_path, &date);
-                        entry.path = Some(path.clone());
-                        std::fs::write(&path, &data).map_err(|_| ()).ok()?;
-                        Ok(data)
+                        let data = self.fetcher.get_log(&entry.url)?;
+                        let root_path = self.root_path.join(&self.channel);
+                        std::fs::create_dir_all(&root_path)?;
+                        let path = make_file_path(&root_path, &date, self.compression);
+                        let mut reader = ChecksumReader::new(data.as_bytes());
+                        self.compression.write_atomic(&path, &mut reader)?;
+                        entry.path = Some(path);
+                        entry.info = Some(FileInfo::new(reader.finish()));
+                        // persisted once in a while instead of on every download, see `flush`
+                        self.dirty = true;
+                        Ok(parse_string(data))
                     }
                 }
             },
             DataLoadMode::Local | DataLoadMode::Prefetch => match entry.path.as_ref() {
-                // read data from path
-                Some(path) => read_path(&path),
-                None => Err(())
+                // map data from path
+                Some(path) => Ok(read_file(&path)?),
+                None => Err(Error::NotCached(*date))
             }
-        };
+        }
+    }
 
-        Some(res.map_or_else(|_| Messages::empty(), |s| parse_string(s)))
+    fn load_stream(&mut self, date: &Date<Utc>) -> Result<Chunks<Error>, Error> {
+        let idx = self.index.binary_search_by_key(date, |l| l.date).map_err(|_| Error::NotInIndex(*date))?;
+        match (&self.mode, self.index[idx].path.as_ref()) {
+            // stream from local file if it is available
+            (DataLoadMode::RemoteAndCache, Some(path))
+            | (DataLoadMode::PrefetchAndCache, Some(path))
+            | (DataLoadMode::Local, Some(path))
+            | (DataLoadMode::Prefetch, Some(path)) => {
+                let reader = Compression::from_path(path).open(path)?;
+                Ok(Box::new(
+                    MessageStream::new(BufReader::new(reader), parse_string).map(|c| c.map_err(Error::from))
+                ))
+            },
+            // otherwise data has to be fully downloaded anyway
+            _ => Ok(Box::new(Some(Ok(self.load(date)?)).into_iter()))
+        }
+    }
+
+    /// Computed from size and checksum of the file, known only for files which were cached
+    fn fingerprint(&self, date: &Date<Utc>) -> Option<u64> {
+        let idx = self.index.binary_search_by_key(date, |l| l.date).ok()?;
+        let info = self.index[idx].info.as_ref()?;
+        Some((info.size << 32) | info.checksum as u64)
     }
 }
 
-fn get_text(client: &Client, url: &String) -> reqwest::Result<String> {
-    client.get(url).send()?.text()
+/// Whether the first line of a file looks like an OverRustle log line. Empty files are
+/// valid logs of days without any messages.
+fn is_log_shaped(first_line: &str) -> bool {
+    let first_line = first_line.trim_end();
+    first_line.is_empty() || parse_line(first_line).is_some()
 }
 
-fn select_urls(client: &Client, url: &String) -> Vec<String> {
+fn select_urls(fetcher: &Fetcher, url: &String) -> Result<Vec<String>, Error> {
     let selector = Selector::parse(".list-group-item").unwrap();
-    let urls = get_text(client, url)
-        .map(|text| {
-            let document = Html::parse_document(text.as_str());
-            let mut urls = Vec::new();
-            document
-                .select(&selector)
-                .for_each(|s| {
-                    urls.push(s.value().attr("href").unwrap().to_string())
-                });
-            urls
-        });
-    match urls {
-        Ok(urls) => urls,
-        Err(err) => {
-            eprintln!("Failed to load overrustle urls from {}: {:?}", url, err);
-            Vec::new()
-        }
+    let text = fetcher.get_text(url)?;
+    let document = Html::parse_document(text.as_str());
+    document
+        .select(&selector)
+        .map(|s| match s.value().attr("href") {
+            Some(href) => Ok(href.to_string()),
+            None => Err(Error::HtmlStructure { url: url.clone(), reason: "list item without href".to_string() })
+        })
+        .collect()
+}
+
+fn parse_date(s: &str, fmt: &str) -> Result<Date<Utc>, Error> {
+    NaiveDate::parse_from_str(s, fmt)
+        .map(|date| Date::<Utc>::from_utc(date, Utc))
+        .map_err(|source| Error::DateParse { input: s.to_string(), source })
+}
+
+fn make_file_path(root_path: &PathBuf, date: &Date<Utc>, compression: Compression) -> PathBuf {
+    root_path.join(date.format("%Y-%m-%d.txt").to_string() + compression.extension())
+}
+
+/// Find file for given date stored with any compression
+fn find_file_path(root_path: &PathBuf, date: &Date<Utc>) -> Option<PathBuf> {
+    Compression::ALL
+        .iter()
+        .map(|c| make_file_path(root_path, date, *c))
+        .find(|path| path.is_file())
+}
+
+/// Read log file, memory-mapping it if it is not compressed
+fn read_file(path: &PathBuf) -> io::Result<Messages> {
+    match Compression::from_path(path) {
+        // This is safe as long as the file is not modified while mapped. Files in the cache are
+        // only ever replaced by renaming new ones over them, which leaves mapped data intact
+        Compression::None => unsafe { parse_file(path) },
+        compression => compression.read_to_string(path).map(parse_string)
     }
 }
 
-fn make_file_path(root_path: &PathBuf, date: &Date<Utc>) -> PathBuf {
-    root_path.join(date.format("%Y-%m-%d.txt").to_string())
+/// Monthly files are stored in `YYYY-MM` directories, user logs in their `userlogs` subdirectory
+fn make_monthly_file_path(root_path: &PathBuf, month: &Date<Utc>, kind: &MonthlyLogKind, compression: Compression) -> PathBuf {
+    root_path
+        .join(month.format("%Y-%m").to_string())
+        .join(kind.file_stem() + ".txt" + compression.extension())
 }
 
-fn get_all_urls_for_channel(client: &Client, channel: &String) -> Vec<LogFileUrl> {
-    let channel_url = format!("{}/{}%20chatlog/", BASE_URL, capitalized(channel));
-    let month_urls = select_urls(&client, &channel_url);
+/// Find monthly file stored with any compression
+fn find_monthly_file_path(root_path: &PathBuf, month: &Date<Utc>, kind: &MonthlyLogKind) -> Option<PathBuf> {
+    Compression::ALL
+        .iter()
+        .map(|c| make_monthly_file_path(root_path, month, kind, *c))
+        .find(|path| path.is_file())
+}
+
+/// Parses month from monthly files directory, e.g. `2019-07`
+fn parse_month_dir(path: &PathBuf) -> Option<Date<Utc>> {
+    if !path.is_dir() {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?;
+    parse_date(&format!("{}-01", name), "%Y-%m-%d").ok()
+}
+
+fn make_month_url(base_url: &str, channel: &String, month: &Date<Utc>) -> String {
+    format!("{}/{}%20chatlog/{}", base_url, capitalized(channel), month.format("%B%%20%Y"))
+}
+
+fn make_monthly_url(base_url: &str, channel: &String, month: &Date<Utc>, kind: &MonthlyLogKind) -> String {
+    format!("{}/{}.txt", make_month_url(base_url, channel, month), kind.file_stem())
+}
+
+fn first_day_of_month(date: &Date<Utc>) -> Date<Utc> {
+    date.with_day(1).unwrap()
+}
+
+fn first_day_of_next_month(date: &Date<Utc>) -> Date<Utc> {
+    let date = first_day_of_month(date);
+    if date.month() == 12 {
+        date.with_year(date.year() + 1).unwrap().with_month(1).unwrap()
+    } else {
+        date.with_month(date.month() + 1).unwrap()
+    }
+}
+
+/// Parses month from month page URL, e.g. `/Channel%20chatlog/July%202019`
+fn parse_month(url: &str) -> Option<Date<Utc>> {
+    let month_str = url.trim_end_matches('/').rsplitn(2, '/').next()?.replace("%20", " ");
+    parse_date(&format!("1 {}", month_str), "%d %B %Y").ok()
+}
+
+/// Scrapes all day and monthly log URLs for channel. If `since` is given, only months starting
+/// from it are scraped.
+fn get_all_urls_for_channel(
+    fetcher: &Fetcher, base_url: &str, channel: &String, since: Option<Date<Utc>>
+) -> Result<(Vec<LogFileUrl>, Vec<MonthlyLogUrl>), Error> {
+    let channel_url = format!("{}/{}%20chatlog/", base_url, capitalized(channel));
+    let month_urls = select_urls(fetcher, &channel_url)?
+        .into_iter()
+        .filter(|url| match (since, parse_month(url)) {
+            (Some(since), Some(month)) => month >= since,
+            // scrape months we can't make sense of, just in case
+            _ => true
+        })
+        .collect::<Vec<_>>();
 
     let bar = make_progress_bar(month_urls.len() * 31);
 
-    let mut day_urls: Vec<LogFileUrl> = month_urls
+    let month_pages: Result<Vec<Vec<String>>, Error> = fetcher.install(|| month_urls
         .par_iter()
-        .flat_map(|url| {
-            let url = format!("{}{}", BASE_URL, url);
-            select_urls(&client, &url)
-        })
-        .filter(|s| {
-            !s.ends_with("userlogs")
-                && !s.ends_with("broadcaster")
-                && !s.ends_with("subscribers")
-                && !s.ends_with("bans")
+        .map(|url| {
+            let url = format!("{}{}", base_url, url);
+            select_urls(fetcher, &url)
         })
-        .filter_map(|s| {
-            bar.inc(1);
-            let parsed_url = LogFileUrl::from_overrustle_url(&s);
-            match parsed_url {
-                Ok(url) => Some(url),
-                Err(err) => {
-                    eprintln!("Error parsing url ({}): {:?}", s, err);
-                    None
-                }
+        .collect()
+    )?;
+
+    let mut monthly_urls = Vec::new();
+    let mut day_urls = Vec::new();
+    for s in month_pages?.into_iter().flatten() {
+        let mut parts = s.trim_end_matches('/').rsplitn(2, '/');
+        let (name, month_url) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        match MonthlyLogKind::from_file_stem(name) {
+            Some(kind) => {
+                let month = parse_month(month_url).ok_or_else(|| Error::HtmlStructure {
+                    url: s.clone(), reason: "monthly log outside of month".to_string()
+                })?;
+                monthly_urls.push(MonthlyLogUrl { url: format!("{}{}.txt", base_url, s), path: None, month, kind });
+            },
+            // users are listed on demand, there are too many of them
+            None if name == "userlogs" => {},
+            None => {
+                bar.inc(1);
+                day_urls.push(LogFileUrl::from_overrustle_url(base_url, &s)?);
             }
-        })
-        .collect();
+        }
+    }
     day_urls.sort_by(|l, r| l.date.cmp(&r.date));
+    monthly_urls.sort_by_key(|m| m.month);
     bar.finish();
-    day_urls
+    Ok((day_urls, monthly_urls))
 }
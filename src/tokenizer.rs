@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::emote_index::EmoteIndex;
+
+/// Splits text of a message into tokens, which are then counted by `ChatLog::slide_token_counts`
+/// and friends
+pub trait Tokenizer: Send + Sync {
+
+    /// Pass every token of `text` to `emit`, in order of appearance. Tokens should borrow from
+    /// `text` whenever possible, since windows may contain millions of them.
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>));
+
+    /// Collect tokens of `text` into a vector
+    fn tokens<'a>(&self, text: &'a str) -> Vec<Cow<'a, str>> {
+        let mut tokens = Vec::new();
+        self.tokenize(text, &mut |tok| tokens.push(tok));
+        tokens
+    }
+
+}
+
+impl<T: Tokenizer + ?Sized> Tokenizer for Box<T> {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        (**self).tokenize(text, emit)
+    }
+}
+
+/// Tokens are separated by ASCII whitespace and kept as they are, punctuation included
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Whitespace;
+
+impl Tokenizer for Whitespace {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        text.split_ascii_whitespace().for_each(|tok| emit(Cow::Borrowed(tok)))
+    }
+}
+
+/// Same as `Whitespace`, but tokens are also separated by non-ASCII whitespace, e.g. no-break
+/// spaces some clients insert to get around duplicate message checks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnicodeWhitespace;
+
+impl Tokenizer for UnicodeWhitespace {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        text.split_whitespace().for_each(|tok| emit(Cow::Borrowed(tok)))
+    }
+}
+
+/// Tokens are words as defined by Unicode word boundaries (UAX #29), so punctuation is dropped.
+/// `@mentions` are skipped entirely.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnicodeWords;
+
+impl Tokenizer for UnicodeWords {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        text.split_whitespace()
+            .filter(|word| !is_mention(word))
+            .flat_map(|word| word.unicode_words())
+            .for_each(|tok| emit(Cow::Borrowed(tok)))
+    }
+}
+
+/// Whitespace-separated words which are known emote codes are kept intact, even if they contain
+/// punctuation (e.g. `D:` or `<3`), the rest is split by `fallback` tokenizer. `@mentions` are
+/// skipped.
+#[derive(Debug, Clone)]
+pub struct EmoteAware<T: Tokenizer = UnicodeWords> {
+    emotes: HashSet<String>,
+    fallback: T,
+}
+
+impl<T: Tokenizer> EmoteAware<T> {
+
+    pub fn new<I: IntoIterator<Item=String>>(emotes: I, fallback: T) -> EmoteAware<T> {
+        EmoteAware { emotes: emotes.into_iter().collect(), fallback }
+    }
+
+    /// Use codes of all the emotes from `index`
+    pub fn from_index(index: &EmoteIndex, fallback: T) -> EmoteAware<T> {
+        EmoteAware::new(index.keys().cloned(), fallback)
+    }
+
+}
+
+impl<T: Tokenizer> Tokenizer for EmoteAware<T> {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        for word in text.split_whitespace() {
+            if self.emotes.contains(word) {
+                emit(Cow::Borrowed(word));
+            } else if !is_mention(word) {
+                self.fallback.tokenize(word, emit);
+            }
+        }
+    }
+}
+
+/// Lowercases tokens produced by the wrapped tokenizer. Tokens which are already lowercase
+/// are not copied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lowercase<T: Tokenizer>(pub T);
+
+impl<T: Tokenizer> Tokenizer for Lowercase<T> {
+    fn tokenize<'a>(&self, text: &'a str, emit: &mut dyn FnMut(Cow<'a, str>)) {
+        self.0.tokenize(text, &mut |tok| {
+            if tok.chars().any(char::is_uppercase) {
+                emit(Cow::Owned(tok.to_lowercase()))
+            } else {
+                emit(tok)
+            }
+        })
+    }
+}
+
+fn is_mention(word: &str) -> bool {
+    word.len() > 1 && word.starts_with('@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens<T: Tokenizer>(tokenizer: &T, text: &str) -> Vec<String> {
+        tokenizer.tokens(text).into_iter().map(|t| t.into_owned()).collect()
+    }
+
+    #[test]
+    fn test_tokenizers() {
+        let text = "@someone Hello,\u{a0}world! D: <3 Kappa";
+        assert_eq!(tokens(&Whitespace, text), vec!["@someone", "Hello,\u{a0}world!", "D:", "<3", "Kappa"]);
+        assert_eq!(tokens(&UnicodeWhitespace, text), vec!["@someone", "Hello,", "world!", "D:", "<3", "Kappa"]);
+        assert_eq!(tokens(&UnicodeWords, text), vec!["Hello", "world", "D", "3", "Kappa"]);
+
+        let emotes = vec!["D:".to_string(), "<3".to_string(), "Kappa".to_string()];
+        let emote_aware = EmoteAware::new(emotes, UnicodeWords);
+        assert_eq!(tokens(&emote_aware, text), vec!["Hello", "world", "D:", "<3", "Kappa"]);
+        assert_eq!(tokens(&Lowercase(emote_aware), text), vec!["hello", "world", "d:", "<3", "kappa"]);
+
+        let boxed: Box<dyn Tokenizer> = Box::new(Lowercase(Whitespace));
+        assert!(boxed.tokens("lower case").iter().all(|t| match t { Cow::Borrowed(_) => true, _ => false }));
+    }
+
+}
@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::io::{self, Read};
+use std::hash::Hash;
 use chrono::Utc;
 use counter::Counter;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -10,7 +13,7 @@ pub fn progress_bar(enabled: bool) {
     PROGRESS_BAR_ENABLED.store(enabled, Ordering::SeqCst)
 }
 
-pub fn most_common(counter: Counter<&str, u64>, threshold: u64) -> Vec<(&str, u64)> {
+pub fn most_common<T: Hash + Eq + Clone>(counter: Counter<T, u64>, threshold: u64) -> Vec<(T, u64)> {
     let mut items = counter.iter()
         .filter_map(|(key, &count)| {
             if count > threshold {
@@ -47,3 +50,67 @@ pub(crate) fn day_after(date: chrono::Date<Utc>) -> chrono::Date<Utc> {
         .expect("Cannot convert from std Duration to chrono Duration");
     date + day
 }
+
+/// Limits rate of events (e.g. requests) to a given number per second. Can be shared between threads.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+
+    /// Create limiter allowing `per_second` events per second, 0 means unlimited
+    pub(crate) fn new(per_second: u32) -> RateLimiter {
+        let interval = if per_second == 0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs(1) / per_second
+        };
+        RateLimiter { interval, next: Mutex::new(Instant::now()) }
+    }
+
+    /// Block current thread until the next event is allowed
+    pub(crate) fn wait(&self) {
+        let delay = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let at = if *next > now { *next } else { now };
+            *next = at + self.interval;
+            at - now
+        };
+        if delay > Duration::from_secs(0) {
+            std::thread::sleep(delay);
+        }
+    }
+
+}
+
+/// Reader which computes size and CRC32 checksum of all the data read through it
+pub(crate) struct ChecksumReader<R: Read> {
+    inner: R,
+    size: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> ChecksumReader<R> {
+
+    pub(crate) fn new(inner: R) -> ChecksumReader<R> {
+        ChecksumReader { inner, size: 0, hasher: crc32fast::Hasher::new() }
+    }
+
+    /// Size and checksum of the data read so far
+    pub(crate) fn finish(&self) -> (u64, u32) {
+        (self.size, self.hasher.clone().finalize())
+    }
+
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.size += n as u64;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use chrono::{Date, DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Minimal HTTP server mimicking OverRustle, which serves logs from a local directory. Directory
+/// should have the same layout as OverRustle cache, i.e. `<root>/<channel>/YYYY-MM-DD.txt` for
+/// daily logs and `<root>/<channel>/YYYY-MM/{broadcaster,subscribers,bans}.txt`,
+/// `<root>/<channel>/YYYY-MM/userlogs/<user>.txt` for monthly ones.
+///
+/// Server serves:
+/// * `/<Channel>%20chatlog/` - list of months available for the channel
+/// * `/<Channel>%20chatlog/<Month>%20<Year>` - list of days and monthly logs available in the month
+/// * `/<Channel>%20chatlog/<Month>%20<Year>/YYYY-MM-DD.txt` - log file itself
+/// * `/<Channel>%20chatlog/<Month>%20<Year>/bans.txt` - monthly log file, the same for
+///   `broadcaster` and `subscribers`
+/// * `/<Channel>%20chatlog/<Month>%20<Year>/userlogs` - list of users in the month
+/// * `/<Channel>%20chatlog/<Month>%20<Year>/userlogs/<user>.txt` - user log file
+///
+/// Log files are served with `ETag` and `Last-Modified` headers, and conditional requests are
+/// answered with `304 Not Modified` when files haven't changed, unless validators are disabled
+/// with `set_validators`. Server runs in a background thread for the rest of the process lifetime.
+#[derive(Debug)]
+pub struct OverRustleMock {
+    addr: SocketAddr,
+    root_path: PathBuf,
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    validators: AtomicBool,
+    /// Path and status code of every request served so far
+    requests: Mutex<Vec<(String, u16)>>,
+}
+
+impl OverRustleMock {
+
+    /// Start serving files from `root_path` on a random local port
+    pub fn start(root_path: PathBuf) -> io::Result<OverRustleMock> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let root = root_path.clone();
+        let state = Arc::new(State { validators: AtomicBool::new(true), requests: Mutex::new(Vec::new()) });
+        let server_state = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let root = root.clone();
+                    let state = server_state.clone();
+                    std::thread::spawn(move || {
+                        // client might have gone away, nothing to do about it
+                        let _ = handle_connection(&root, &state, stream);
+                    });
+                }
+            }
+        });
+        Ok(OverRustleMock { addr, root_path, state })
+    }
+
+    /// URL to be passed to `OverRustleLogs::with_base_url`
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn root_path(&self) -> &Path {
+        &self.root_path
+    }
+
+    /// Whether to send `ETag` and `Last-Modified` headers and answer conditional requests, like
+    /// a server which doesn't support them
+    pub fn set_validators(&self, enabled: bool) {
+        self.state.validators.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Number of requests served so far with given status code
+    pub fn count_requests(&self, status: u16) -> usize {
+        self.state.requests.lock().unwrap().iter().filter(|(_, s)| *s == status).count()
+    }
+
+    /// Number of requests served so far for paths ending with `suffix`, e.g. a file name
+    pub fn count_requests_to(&self, suffix: &str) -> usize {
+        self.state.requests.lock().unwrap().iter().filter(|(p, _)| p.ends_with(suffix)).count()
+    }
+
+}
+
+/// Response to a single request
+struct Response {
+    content_type: &'static str,
+    body: Vec<u8>,
+    /// Modification time of the file served, None for listings
+    modified: Option<SystemTime>,
+}
+
+fn handle_connection(root_path: &Path, state: &State, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // requests never have body, only conditional headers matter
+    let mut if_none_match = None;
+    let mut if_modified_since = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        let mut kv = header.splitn(2, ':');
+        let (name, value) = (kv.next().unwrap_or("").trim().to_ascii_lowercase(), kv.next().unwrap_or("").trim().to_string());
+        match name.as_str() {
+            "if-none-match" => if_none_match = Some(value),
+            "if-modified-since" => if_modified_since = Some(value),
+            _ => {}
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let path = parts.next().and_then(|method| if method == "GET" { parts.next() } else { None });
+    let response = path.and_then(|path| route(root_path, path));
+
+    let mut stream = stream;
+    let validators = state.validators.load(Ordering::SeqCst);
+    let mut headers = String::new();
+    let (status, content_type, body) = match response {
+        Some(Response { content_type, body, modified: Some(modified) }) if validators => {
+            let etag = format!("\"{:08x}\"", crc32fast::hash(&body));
+            let modified = DateTime::<Utc>::from(modified);
+            headers = format!("ETag: {}\r\nLast-Modified: {}\r\n", etag, modified.format(HTTP_DATE_FORMAT));
+            // `If-None-Match` takes precedence over `If-Modified-Since`
+            let not_modified = match (if_none_match, if_modified_since) {
+                (Some(tags), _) => tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"),
+                (None, Some(since)) => NaiveDateTime::parse_from_str(&since, HTTP_DATE_FORMAT)
+                    .map_or(false, |since| modified.naive_utc().timestamp() <= since.timestamp()),
+                (None, None) => false
+            };
+            if not_modified {
+                (304, content_type, Vec::new())
+            } else {
+                (200, content_type, body)
+            }
+        },
+        Some(Response { content_type, body, .. }) => (200, content_type, body),
+        None => (404, "text/plain", b"Not Found".to_vec())
+    };
+    state.requests.lock().unwrap().push((path.unwrap_or("").to_string(), status));
+
+    let reason = match status {
+        200 => "OK",
+        304 => "Not Modified",
+        _ => "Not Found",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status, reason, content_type, body.len(), headers
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// Returns response for a given path, or None if there is nothing to serve
+fn route(root_path: &Path, path: &str) -> Option<Response> {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let channel = segments.first()?.replace("%20", " ");
+    if !channel.ends_with(" chatlog") {
+        return None;
+    }
+    let channel_path = find_channel_dir(root_path, &channel[..channel.len() - " chatlog".len()])?;
+    let dates = list_dates(&channel_path);
+
+    match segments.as_slice() {
+        [channel] => {
+            let months = dates.iter()
+                .map(|d| (d.year(), d.month()))
+                .collect::<BTreeSet<_>>();
+            let links = months.iter()
+                .filter_map(|(y, m)| NaiveDate::from_ymd_opt(*y, *m, 1))
+                .map(|d| format!("/{}/{}", channel, d.format("%B%%20%Y")))
+                .collect::<Vec<_>>();
+            Some(listing(&links))
+        },
+        [channel, month] => {
+            let month_name = month.replace("%20", " ");
+            let mut links = dates.iter()
+                .filter(|d| d.format("%B %Y").to_string() == month_name)
+                .map(|d| format!("/{}/{}/{}", channel, month, d.format("%Y-%m-%d")))
+                .collect::<Vec<_>>();
+            if links.is_empty() {
+                return None;
+            }
+            let month_path = channel_path.join(parse_month(month)?);
+            if month_path.join("userlogs").is_dir() {
+                links.push(format!("/{}/{}/userlogs", channel, month));
+            }
+            for extra in &["broadcaster", "subscribers", "bans"] {
+                if month_path.join(format!("{}.txt", extra)).is_file() {
+                    links.push(format!("/{}/{}/{}", channel, month, extra));
+                }
+            }
+            Some(listing(&links))
+        },
+        [channel, month, "userlogs"] => {
+            let links = std::fs::read_dir(channel_path.join(parse_month(month)?).join("userlogs")).ok()?
+                .filter_map(|e| e.ok()?.file_name().into_string().ok())
+                .filter(|name| name.ends_with(".txt"))
+                .map(|name| format!("/{}/{}/userlogs/{}", channel, month, name.trim_end_matches(".txt")))
+                .collect::<Vec<_>>();
+            Some(listing(&links))
+        },
+        [_, month, "userlogs", file] if file.ends_with(".txt") => {
+            log_file(&channel_path.join(parse_month(month)?).join("userlogs").join(file))
+        },
+        [_, month, file] if file.ends_with(".txt") => {
+            // daily logs are in channel directory, monthly ones in month directory
+            log_file(&channel_path.join(file))
+                .or_else(|| log_file(&channel_path.join(parse_month(month)?).join(file)))
+        },
+        _ => None
+    }
+}
+
+/// Converts month path segment, e.g. `July%202019`, into directory name, e.g. `2019-07`
+fn parse_month(segment: &str) -> Option<String> {
+    let month = NaiveDate::parse_from_str(&format!("1 {}", segment.replace("%20", " ")), "%d %B %Y").ok()?;
+    Some(month.format("%Y-%m").to_string())
+}
+
+fn listing(links: &[String]) -> Response {
+    let items = links.iter()
+        .map(|l| format!("<a class=\"list-group-item\" href=\"{}\">{}</a>", l, l))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let body = format!("<html><body><div class=\"list-group\">\n{}\n</div></body></html>", items);
+    Response { content_type: "text/html", body: body.into_bytes(), modified: None }
+}
+
+fn log_file(path: &Path) -> Option<Response> {
+    let body = std::fs::read(path).ok()?;
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(Response { content_type: "text/plain; charset=utf-8", body, modified: Some(modified) })
+}
+
+/// Channel names are case-insensitive on OverRustle
+fn find_channel_dir(root_path: &Path, channel: &str) -> Option<PathBuf> {
+    std::fs::read_dir(root_path).ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.is_dir() && p.file_name()
+            .and_then(|f| f.to_str())
+            .map_or(false, |f| f.eq_ignore_ascii_case(channel)))
+}
+
+fn list_dates(channel_path: &Path) -> Vec<Date<Utc>> {
+    let mut dates = std::fs::read_dir(channel_path)
+        .map(|entries| entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().into_string().ok()?;
+                NaiveDate::parse_from_str(&name, "%Y-%m-%d.txt").ok()
+            })
+            .map(|d| Date::<Utc>::from_utc(d, Utc))
+            .collect::<Vec<_>>())
+        .unwrap_or_else(|_| Vec::new());
+    dates.sort();
+    dates
+}
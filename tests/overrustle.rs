@@ -0,0 +1,314 @@
+mod common;
+
+use chatan::chatlog::DailyChatLog;
+use chatan::overrustle::{DataLoadMode, Error, MonthlyLogKind, OverRustleLogs};
+use chatan::compression::Compression;
+
+use chrono::{Date, Duration, NaiveDate, Utc};
+use std::path::PathBuf;
+
+use common::OverRustleMock;
+
+const CHANNEL: &str = "somechannel";
+
+const DAYS: [(&str, &str); 3] = [
+    ("2019-06-30", "[2019-06-30 23:59:59 UTC] user1: FeelsGoodMan\n"),
+    ("2019-07-01", "[2019-07-01 00:00:42 UTC] user2: WE ARE READY\n[2019-07-01 00:00:43 UTC] user3: PogChamp\n"),
+    ("2019-07-02", "[2019-07-02 12:00:00 UTC] user1: FeelsBadMan\n"),
+];
+
+const MONTHLY: [(&str, &str); 4] = [
+    ("broadcaster.txt", "[2019-07-01 00:00:40 UTC] somechannel: hello chat\n"),
+    ("subscribers.txt", "[2019-07-01 00:00:41 UTC] twitchnotify: user2 subscribed for 5 months in a row!\n"),
+    ("bans.txt", "[2019-07-02 12:00:01 UTC] Ban: user1 timed out for 600 seconds\n"),
+    ("userlogs/user1.txt", "[2019-07-02 12:00:00 UTC] user1: FeelsBadMan\n"),
+];
+
+/// Creates an empty directory unique for a given test
+fn make_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chatan-test-{}-{}", name, std::process::id()));
+    if path.exists() {
+        std::fs::remove_dir_all(&path).expect("Could not clean up test directory");
+    }
+    std::fs::create_dir_all(&path).expect("Could not create test directory");
+    path
+}
+
+fn start_server(name: &str) -> OverRustleMock {
+    let root = make_dir(&format!("{}-server", name));
+    std::fs::create_dir_all(root.join(CHANNEL)).expect("Could not create channel directory");
+    for (date, data) in DAYS.iter() {
+        std::fs::write(root.join(CHANNEL).join(format!("{}.txt", date)), data)
+            .expect("Could not write log file");
+    }
+    std::fs::create_dir_all(root.join(CHANNEL).join("2019-07").join("userlogs")).expect("Could not create month directory");
+    for (file, data) in MONTHLY.iter() {
+        std::fs::write(root.join(CHANNEL).join("2019-07").join(file), data).expect("Could not write log file");
+    }
+    OverRustleMock::start(root).expect("Could not start mock server")
+}
+
+fn date(s: &str) -> Date<Utc> {
+    Date::from_utc(NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap(), Utc)
+}
+
+fn sync(server: &OverRustleMock, cache: &PathBuf, mode: DataLoadMode) -> OverRustleLogs {
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), mode)
+        .with_base_url(server.base_url());
+    logs.sync().expect("Could not sync logs");
+    logs
+}
+
+/// Sync with every day considered recent, so that all of them are downloaded again
+fn sync_recent(server: &OverRustleMock, cache: &PathBuf) -> OverRustleLogs {
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::PrefetchAndCache)
+        .with_base_url(server.base_url())
+        .with_refresh_horizon(Duration::days(365 * 1000));
+    logs.sync().expect("Could not sync logs");
+    assert!(logs.failed_downloads().is_empty());
+    logs
+}
+
+fn cached_files(cache: &PathBuf) -> usize {
+    std::fs::read_dir(cache.join(CHANNEL)).map_or(0, |entries| entries
+        .filter(|e| e.as_ref().map_or(false, |e| e.file_name().to_string_lossy().ends_with(".txt")))
+        .count())
+}
+
+fn check_loads(logs: &mut OverRustleLogs) {
+    assert_eq!(logs.range(), Some((date("2019-06-30"), date("2019-07-02"))));
+    let messages = logs.load(&date("2019-07-01")).expect("Could not load logs");
+    let users = messages.vec().iter().map(|m| m.user()).collect::<Vec<_>>();
+    assert_eq!(users, vec!["user2", "user3"]);
+    assert!(logs.load(&date("2019-07-03")).is_err());
+}
+
+#[test]
+fn test_sync_remote() {
+    let server = start_server("remote");
+    let cache = make_dir("remote-cache");
+    let mut logs = sync(&server, &cache, DataLoadMode::Remote);
+    check_loads(&mut logs);
+    assert_eq!(cached_files(&cache), 0);
+}
+
+#[test]
+fn test_sync_remote_and_cache() {
+    let server = start_server("remote-and-cache");
+    let cache = make_dir("remote-and-cache-cache");
+    let mut logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    assert_eq!(cached_files(&cache), 0);
+    check_loads(&mut logs);
+    assert_eq!(cached_files(&cache), 1);
+}
+
+#[test]
+fn test_sync_prefetch() {
+    for (name, mode) in vec![("prefetch", DataLoadMode::Prefetch), ("prefetch-and-cache", DataLoadMode::PrefetchAndCache)] {
+        let server = start_server(name);
+        let cache = make_dir(&format!("{}-cache", name));
+        let mut logs = sync(&server, &cache, mode);
+        assert!(logs.failed_downloads().is_empty());
+        assert_eq!(cached_files(&cache), DAYS.len());
+        check_loads(&mut logs);
+    }
+}
+
+#[test]
+fn test_sync_local() {
+    let server = start_server("local");
+    let cache = make_dir("local-cache");
+    sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+    // local mode should not make any requests, so pointing it to nowhere should be fine
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::Local)
+        .with_base_url("http://127.0.0.1:1".to_string());
+    logs.sync().expect("Could not sync logs");
+    check_loads(&mut logs);
+}
+
+#[test]
+fn test_incremental_sync() {
+    let server = start_server("incremental");
+    let cache = make_dir("incremental-cache");
+    sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+
+    // only the last known month and the ones after it should be scraped again
+    let channel_path = server.root_path().join(CHANNEL);
+    for date in &["2019-06-15", "2019-07-15", "2019-08-01"] {
+        std::fs::write(channel_path.join(format!("{}.txt", date)), "").expect("Could not write log file");
+    }
+    let logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    let dates = logs.index().iter().map(|l| l.date().format("%Y-%m-%d").to_string()).collect::<Vec<_>>();
+    assert_eq!(dates, vec!["2019-06-30", "2019-07-01", "2019-07-02", "2019-07-15", "2019-08-01"]);
+    assert!(logs.index()[0].info().is_some());
+
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::RemoteAndCache)
+        .with_base_url(server.base_url())
+        .with_full_resync(true);
+    logs.sync().expect("Could not sync logs");
+    assert_eq!(logs.index().len(), 6);
+}
+
+#[test]
+fn test_refresh_recent_days() {
+    let server = start_server("refresh");
+    let cache = make_dir("refresh-cache");
+    // without validators every refresh is a full download, so only the contents tell what changed
+    server.set_validators(false);
+    let infos = |logs: &OverRustleLogs| logs.index().iter()
+        .map(|l| l.info().cloned().expect("File info should be known"))
+        .collect::<Vec<_>>();
+
+    let before = infos(&sync_recent(&server, &cache));
+
+    // every day is recent, but only the changed one should be rewritten
+    let changed = "[2019-07-02 12:00:00 UTC] user1: FeelsBadMan\n[2019-07-02 12:00:01 UTC] user2: LUL\n";
+    std::fs::write(server.root_path().join(CHANNEL).join("2019-07-02.txt"), changed)
+        .expect("Could not write log file");
+    let mut logs = sync_recent(&server, &cache);
+    let after = infos(&logs);
+    assert_eq!(server.count_requests(304), 0);
+    // info, including the time of download, is only replaced when file is rewritten
+    assert_eq!(before[..2], after[..2]);
+    assert_ne!(before[2].checksum, after[2].checksum);
+    assert_eq!(after[2].size, changed.len() as u64);
+    assert_eq!(logs.load(&date("2019-07-02")).expect("Could not load logs").vec().len(), 2);
+}
+
+#[test]
+fn test_conditional_refresh() {
+    let server = start_server("conditional");
+    let cache = make_dir("conditional-cache");
+    let logs = sync_recent(&server, &cache);
+    let before = logs.index().iter().map(|l| l.info().cloned().expect("File info should be known")).collect::<Vec<_>>();
+    assert!(before.iter().all(|info| info.etag.is_some() && info.last_modified.is_some()));
+    assert_eq!(server.count_requests(304), 0);
+
+    // nothing has changed, so server should answer every conditional request with 304
+    let logs = sync_recent(&server, &cache);
+    assert_eq!(server.count_requests(304), DAYS.len());
+    let after = logs.index().iter().map(|l| l.info().cloned().expect("File info should be known")).collect::<Vec<_>>();
+    assert_eq!(before, after);
+
+    // without validators files are downloaded again, but kept since their contents are the same
+    server.set_validators(false);
+    let logs = sync_recent(&server, &cache);
+    assert_eq!(server.count_requests(304), DAYS.len());
+    let after = logs.index().iter().map(|l| l.info().cloned().expect("File info should be known")).collect::<Vec<_>>();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn test_monthly_logs() {
+    let server = start_server("monthly");
+    let cache = make_dir("monthly-cache");
+    let mut logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    let kinds = logs.monthly_index().iter().map(|m| m.kind().clone()).collect::<Vec<_>>();
+    assert_eq!(kinds.len(), 3);
+    assert!(kinds.contains(&MonthlyLogKind::Bans));
+    assert!(logs.monthly_index().iter().all(|m| m.month() == date("2019-07-01")));
+
+    let bans = logs.load_bans(&date("2019-07-02")).expect("Could not load bans");
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].user, "user1");
+    let subs = logs.load_subscriptions(&date("2019-07-02")).expect("Could not load subscriptions");
+    assert_eq!((subs[0].user.as_str(), subs[0].months), ("user2", 5));
+    assert_eq!(logs.load_broadcaster(&date("2019-07-02")).expect("Could not load broadcaster").vec().len(), 1);
+    assert_eq!(logs.list_users(&date("2019-07-02")).expect("Could not list users"), vec!["user1"]);
+    assert_eq!(logs.load_user_log(&date("2019-07-02"), "user1").expect("Could not load user log").vec().len(), 1);
+    assert!(logs.load_bans(&date("2019-06-30")).is_err());
+
+    // everything loaded above should have been cached
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::Local)
+        .with_base_url("http://127.0.0.1:1".to_string());
+    logs.sync().expect("Could not sync logs");
+    assert_eq!(logs.monthly_index().len(), 3);
+    assert_eq!(logs.load_bans(&date("2019-07-02")).expect("Could not load bans").len(), 1);
+    assert_eq!(logs.list_users(&date("2019-07-02")).expect("Could not list users"), vec!["user1"]);
+    // month is known, but this user log was never downloaded
+    match logs.load_user_log(&date("2019-07-02"), "user2") {
+        Err(Error::NotCached(month)) => assert_eq!(month, date("2019-07-01")),
+        other => panic!("Unexpected result: {:?}", other.map(|m| m.vec().len())),
+    }
+}
+
+#[test]
+fn test_recompress() {
+    let server = start_server("recompress");
+    let cache = make_dir("recompress-cache");
+    let mut logs = sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+    logs.recompress(Compression::Gzip).expect("Could not recompress logs");
+    let channel_path = cache.join(CHANNEL);
+    for (d, _) in DAYS.iter() {
+        assert!(channel_path.join(format!("{}.txt.gz", d)).is_file());
+        assert!(!channel_path.join(format!("{}.txt", d)).exists());
+        assert!(!channel_path.join(format!("{}.txt.part", d)).exists());
+    }
+
+    // copy left behind by interrupted recompression should lose to the configured compression
+    std::fs::write(channel_path.join("2019-07-01.txt"), "[2019-07-01 00:00:42 UTC] user4: stale\n")
+        .expect("Could not write log file");
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::Local)
+        .with_compression(Compression::Gzip);
+    logs.sync().expect("Could not sync logs");
+    check_loads(&mut logs);
+}
+
+#[test]
+fn test_persist_cached_on_load() {
+    let server = start_server("persist");
+    let cache = make_dir("persist-cache");
+    {
+        let mut logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+        logs.load(&date("2019-07-01")).expect("Could not load logs");
+        // index is persisted when logs are dropped
+    }
+    let mut logs = OverRustleLogs::new(cache.clone(), CHANNEL.to_string(), DataLoadMode::Local);
+    logs.sync().expect("Could not sync logs");
+    assert_eq!(logs.index().len(), 1);
+    assert!(logs.index()[0].info().is_some());
+
+    // recompressing local logs should keep days which are not cached yet in persisted index
+    logs.recompress(Compression::Gzip).expect("Could not recompress logs");
+    drop(logs);
+    let logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    let dates = logs.index().iter().map(|l| l.date().format("%Y-%m-%d").to_string()).collect::<Vec<_>>();
+    assert_eq!(dates, DAYS.iter().map(|(d, _)| d.to_string()).collect::<Vec<_>>());
+    let cached = &logs.index()[1];
+    assert!(cached.path().map_or(false, |p| p.to_string_lossy().ends_with(".txt.gz")));
+    assert!(cached.info().is_some());
+}
+
+#[test]
+fn test_corrupt_manifest() {
+    let server = start_server("corrupt");
+    let cache = make_dir("corrupt-cache");
+    sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+    std::fs::write(cache.join(CHANNEL).join("manifest.json"), "{ not json").expect("Could not write manifest");
+    // broken index should cause full resync instead of failing
+    let mut logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    assert!(logs.manifest_error().is_some());
+    check_loads(&mut logs);
+    assert_eq!(logs.index().len(), DAYS.len());
+    // index is rewritten by the resync, so the next one can use it again
+    let logs = sync(&server, &cache, DataLoadMode::RemoteAndCache);
+    assert!(logs.manifest_error().is_none());
+}
+
+#[test]
+fn test_empty_day() {
+    let server = start_server("empty");
+    let cache = make_dir("empty-cache");
+    std::fs::write(server.root_path().join(CHANNEL).join("2019-06-29.txt"), "").expect("Could not write log file");
+    let mut logs = sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+    assert!(logs.failed_downloads().is_empty());
+    assert_eq!(logs.load(&date("2019-06-29")).expect("Could not load empty day").vec().len(), 0);
+    assert_eq!(server.count_requests_to("2019-06-29.txt"), 1);
+    drop(logs);
+
+    // empty day is recorded in index, so it is not downloaded again
+    let mut logs = sync(&server, &cache, DataLoadMode::PrefetchAndCache);
+    assert!(logs.index()[0].path().is_some());
+    assert_eq!(logs.load(&date("2019-06-29")).expect("Could not load empty day").vec().len(), 0);
+    assert_eq!(server.count_requests_to("2019-06-29.txt"), 1);
+}